@@ -0,0 +1,340 @@
+//! Find days on which query shapes or subgraphs behaved unusually. Each
+//! day is compared with a baseline made from the days before it: for
+//! every shape, we look at its number of calls, its average time and the
+//! percentage of slow calls, and for every subgraph at its number of
+//! calls. A value is unusual if its robust z-score, which uses the median
+//! and the median absolute deviation (MAD) of the baseline instead of the
+//! mean and standard deviation, is above a threshold. That makes the
+//! baseline insensitive to earlier outliers. We also report shapes that
+//! we have never seen before and that take a large share of the day's
+//! time. Only increases are reported since those are what hurts
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::{regroup, QueryInfo};
+
+/// The robust z-score above which we report a value unless told
+/// otherwise; this is the usual cutoff for the modified z-score
+pub const DEFAULT_THRESHOLD: f64 = 3.5;
+
+/// How many preceding days form the baseline unless told otherwise
+pub const DEFAULT_WINDOW: usize = 14;
+
+/// Shapes and subgraphs with fewer calls than this on a day are ignored
+/// for that day unless told otherwise
+pub const DEFAULT_MIN_CALLS: u64 = 100;
+
+/// How many days of history a shape or subgraph needs before we compare
+/// it with its baseline
+const MIN_HISTORY: usize = 3;
+
+/// The share of a day's total time above which a shape that we have
+/// never seen before is reported
+const NEW_SHARE: f64 = 0.01;
+
+/// Scales the MAD so that it estimates the standard deviation for
+/// normally distributed values
+const MAD_SCALE: f64 = 1.4826;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A shape deviates from its baseline
+    Shape,
+    /// A shape we have never seen before takes a large share of the time
+    NewShape,
+    /// A subgraph gets a lot more calls than usual
+    Subgraph,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Kind::Shape => "shape",
+            Kind::NewShape => "new-shape",
+            Kind::Subgraph => "subgraph",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Anomaly {
+    pub day: String,
+    pub kind: Kind,
+    pub subgraph: String,
+    /// The shape hash; `None` for subgraphs
+    pub hash: Option<u64>,
+    /// The query of the shape; `None` for subgraphs
+    pub query: Option<String>,
+    /// What deviated: `calls`, `avg_time`, `slow_percent`, or
+    /// `time_share` for new shapes
+    pub metric: &'static str,
+    pub value: f64,
+    /// The median of the metric over the baseline
+    pub baseline: f64,
+    /// The robust z-score; `None` for new shapes, which have no baseline
+    pub score: Option<f64>,
+}
+
+/// A statistic of a shape, and the smallest spread we assume for its
+/// baseline so that a constant baseline does not make every tiny change
+/// look unusual
+struct Metric {
+    name: &'static str,
+    value: fn(&QueryInfo) -> f64,
+    min_spread: f64,
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "calls",
+        value: |info| info.calls as f64,
+        min_spread: 1.0,
+    },
+    Metric {
+        name: "avg_time",
+        value: |info| info.avg(),
+        min_spread: 1.0,
+    },
+    Metric {
+        name: "slow_percent",
+        value: |info| info.slow_count as f64 * 100.0 / info.calls as f64,
+        min_spread: 0.5,
+    },
+];
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// The median of `history` and the robust z-score of `value` against it
+fn score(value: f64, mut history: Vec<f64>, min_spread: f64) -> (f64, f64) {
+    let med = median(&mut history);
+    let mut deviations: Vec<f64> = history.iter().map(|v| (v - med).abs()).collect();
+    let spread = (MAD_SCALE * median(&mut deviations))
+        .max(med.abs() * 0.01)
+        .max(min_spread);
+    (med, (value - med) / spread)
+}
+
+/// One day of summaries, with all groups of a shape combined
+struct Day<'a> {
+    name: &'a str,
+    shapes: BTreeMap<u64, QueryInfo>,
+    /// The number of calls for each subgraph
+    subgraphs: BTreeMap<&'a str, u64>,
+    total_time: u64,
+}
+
+impl<'a> Day<'a> {
+    fn new(name: &'a str, infos: &'a [QueryInfo]) -> Self {
+        let mut subgraphs = BTreeMap::new();
+        for info in infos {
+            *subgraphs.entry(info.subgraph.as_str()).or_default() += info.calls;
+        }
+        let shapes = regroup(infos.to_vec(), &[])
+            .into_iter()
+            .map(|info| (info.hash, info))
+            .collect();
+        Day {
+            name,
+            shapes,
+            subgraphs,
+            total_time: infos.iter().map(|info| info.total_time).sum(),
+        }
+    }
+}
+
+/// Find anomalies in `days`, which are pairs of the name of a day and its
+/// summaries, oldest first. Each day is compared with up to `window` days
+/// before it; values need a robust z-score above `threshold` and at least
+/// `min_calls` calls to be reported. The result is sorted by day, and
+/// the most unusual anomalies of a day come first
+pub fn detect(
+    days: &[(String, Vec<QueryInfo>)],
+    threshold: f64,
+    window: usize,
+    min_calls: u64,
+) -> Vec<Anomaly> {
+    let days: Vec<_> = days
+        .iter()
+        .map(|(name, infos)| Day::new(name, infos))
+        .collect();
+    let mut seen = BTreeSet::new();
+    let mut anomalies = Vec::new();
+    for (indx, day) in days.iter().enumerate() {
+        let baseline = &days[indx.saturating_sub(window)..indx];
+        let mut found = Vec::new();
+        for (hash, info) in &day.shapes {
+            let history: Vec<&QueryInfo> = baseline
+                .iter()
+                .filter_map(|day| day.shapes.get(hash))
+                .collect();
+            let anomaly = |metric: &'static str, value, baseline, score: Option<f64>| Anomaly {
+                day: day.name.to_owned(),
+                kind: if score.is_some() {
+                    Kind::Shape
+                } else {
+                    Kind::NewShape
+                },
+                subgraph: info.subgraph.clone(),
+                hash: Some(*hash),
+                query: Some(info.query.clone()),
+                metric,
+                value,
+                baseline,
+                score,
+            };
+
+            if history.is_empty() {
+                let share = info.total_time as f64 / day.total_time.max(1) as f64;
+                // On the first days, every shape is new
+                if indx >= MIN_HISTORY && !seen.contains(hash) && share >= NEW_SHARE {
+                    found.push(anomaly("time_share", share * 100.0, 0.0, None));
+                }
+                continue;
+            }
+            if history.len() < MIN_HISTORY || info.calls < min_calls {
+                continue;
+            }
+            for metric in METRICS {
+                let values = if metric.name == "calls" {
+                    // Days on which the shape did not appear count as 0
+                    // calls
+                    baseline
+                        .iter()
+                        .map(|day| day.shapes.get(hash).map_or(0.0, metric.value))
+                        .collect()
+                } else {
+                    history.iter().map(|info| (metric.value)(info)).collect()
+                };
+                let value = (metric.value)(info);
+                let (med, z) = score(value, values, metric.min_spread);
+                if z > threshold {
+                    found.push(anomaly(metric.name, value, med, Some(z)));
+                }
+            }
+        }
+
+        for (subgraph, calls) in &day.subgraphs {
+            let present = baseline
+                .iter()
+                .filter(|day| day.subgraphs.contains_key(subgraph))
+                .count();
+            if present < MIN_HISTORY || *calls < min_calls {
+                continue;
+            }
+            let values = baseline
+                .iter()
+                .map(|day| day.subgraphs.get(subgraph).copied().unwrap_or(0) as f64)
+                .collect();
+            let (med, z) = score(*calls as f64, values, 1.0);
+            if z > threshold {
+                found.push(Anomaly {
+                    day: day.name.to_owned(),
+                    kind: Kind::Subgraph,
+                    subgraph: subgraph.to_string(),
+                    hash: None,
+                    query: None,
+                    metric: "calls",
+                    value: *calls as f64,
+                    baseline: med,
+                    score: Some(z),
+                });
+            }
+        }
+
+        // New shapes first, then by score
+        found.sort_by(|a, b| {
+            let a = a.score.unwrap_or(f64::INFINITY);
+            let b = b.score.unwrap_or(f64::INFINITY);
+            b.partial_cmp(&a).unwrap()
+        });
+        anomalies.extend(found);
+        seen.extend(day.shapes.keys().copied());
+    }
+    anomalies
+}
+
+/// The day a summary file is for if its name has the form
+/// `YYYY-MM-DD.<extension>`
+pub fn day_of(filename: &str) -> Option<&str> {
+    let day = filename.split('.').next()?;
+    let valid = day.len() == 10
+        && day.bytes().enumerate().all(|(pos, b)| match pos {
+            4 | 7 => b == b'-',
+            _ => b.is_ascii_digit(),
+        });
+    if valid && day.len() < filename.len() {
+        Some(day)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::fixtures::info;
+
+    fn shape(query: &str, subgraph: &str, calls: u64, avg: u64, slow: u64) -> QueryInfo {
+        let mut info = info(0, subgraph, query, calls, calls * avg);
+        info.slow_count = slow;
+        info
+    }
+
+    #[test]
+    fn find_anomalies() {
+        assert_eq!(Some("2024-05-01"), day_of("2024-05-01.jsonl"));
+        assert_eq!(None, day_of("2024-05-01"));
+        assert_eq!(None, day_of("summary.jsonl"));
+
+        let steady = |day: usize| {
+            vec![
+                shape("{ a { id } }", "QmA", 1000 + day as u64 * 10, 50, 0),
+                shape("{ b { id } }", "QmA", 500, 20 + day as u64 % 2, 1),
+                shape("{ c { id } }", "QmB", 2000, 10, 0),
+            ]
+        };
+        let mut days: Vec<_> = (1..=6)
+            .map(|day| (format!("2024-05-0{}", day), steady(day)))
+            .collect();
+        let mut last = steady(7);
+        // `b` gets slow, `QmB` gets a lot more traffic
+        last[1] = shape("{ b { id } }", "QmA", 500, 400, 60);
+        last[2].calls = 20000;
+        last.push(shape("{ new { id } }", "QmA", 10, 5000, 0));
+        days.push(("2024-05-07".to_owned(), last));
+
+        let anomalies = detect(&days, DEFAULT_THRESHOLD, DEFAULT_WINDOW, DEFAULT_MIN_CALLS);
+        let found: Vec<_> = anomalies
+            .iter()
+            .map(|a| {
+                (
+                    a.day.as_str(),
+                    a.kind,
+                    a.subgraph.as_str(),
+                    a.query.as_deref().unwrap_or(""),
+                    a.metric,
+                )
+            })
+            .collect();
+        let day = "2024-05-07";
+        assert_eq!(5, found.len());
+        // New shapes come first
+        assert_eq!(
+            (day, Kind::NewShape, "QmA", "{ new { id } }", "time_share"),
+            found[0]
+        );
+        assert!(found.contains(&(day, Kind::Shape, "QmA", "{ b { id } }", "avg_time")));
+        assert!(found.contains(&(day, Kind::Shape, "QmA", "{ b { id } }", "slow_percent")));
+        assert!(found.contains(&(day, Kind::Shape, "QmB", "{ c { id } }", "calls")));
+        assert!(found.contains(&(day, Kind::Subgraph, "QmB", "", "calls")));
+    }
+}
@@ -0,0 +1,226 @@
+//! Group query shapes that are similar but not identical, e.g. because
+//! one of them selects an additional scalar field. Each shape gets a
+//! structural fingerprint, the set of its root fields, the arguments it
+//! uses and the paths of all fields it selects, and shapes whose
+//! fingerprints are similar enough end up in the same cluster
+use graphql_parser::query as q;
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::filters::{attributes, resolve};
+use crate::{shape_hash, QueryInfo};
+
+/// The similarity above which two shapes are put into the same cluster
+/// unless told otherwise
+pub const DEFAULT_THRESHOLD: f64 = 0.8;
+
+struct Fingerprinter<'a> {
+    fragments: HashMap<&'a str, &'a q::FragmentDefinition>,
+    vars: Map<String, Value>,
+    /// The fragments we are currently expanding
+    stack: Vec<&'a str>,
+    features: BTreeSet<String>,
+}
+
+impl<'a> Fingerprinter<'a> {
+    fn walk(&mut self, set: &'a q::SelectionSet, path: &str) {
+        for item in &set.items {
+            match item {
+                q::Selection::Field(field) => {
+                    if field.name == "__typename" {
+                        continue;
+                    }
+                    let path = if path.is_empty() {
+                        self.features.insert(format!("root:{}", field.name));
+                        field.name.clone()
+                    } else {
+                        format!("{}.{}", path, field.name)
+                    };
+                    let mut attrs = BTreeSet::new();
+                    for (name, value) in &field.arguments {
+                        attributes(name, &resolve(value, &self.vars), false, &mut attrs);
+                    }
+                    for attr in attrs {
+                        self.features.insert(format!("arg:{}({})", path, attr));
+                    }
+                    self.features.insert(format!("field:{}", path));
+                    self.walk(&field.selection_set, &path);
+                }
+                q::Selection::FragmentSpread(spread) => {
+                    let name = spread.fragment_name.as_str();
+                    if self.stack.contains(&name) {
+                        continue;
+                    }
+                    if let Some(frag) = self.fragments.get(name).copied() {
+                        self.stack.push(name);
+                        self.walk(&frag.selection_set, path);
+                        self.stack.pop();
+                    }
+                }
+                q::Selection::InlineFragment(frag) => self.walk(&frag.selection_set, path),
+            }
+        }
+    }
+}
+
+/// The structural fingerprint of `doc`, with arguments taken from
+/// `variables` if they are passed that way
+pub fn fingerprint(doc: &q::Document, variables: &str) -> BTreeSet<String> {
+    let fragments = doc
+        .definitions
+        .iter()
+        .filter_map(|defn| match defn {
+            q::Definition::Fragment(frag) => Some((frag.name.as_str(), frag)),
+            q::Definition::Operation(_) => None,
+        })
+        .collect();
+    let vars = match serde_json::from_str(variables) {
+        Ok(Value::Object(vars)) => vars,
+        _ => Map::new(),
+    };
+    let mut printer = Fingerprinter {
+        fragments,
+        vars,
+        stack: Vec::new(),
+        features: BTreeSet::new(),
+    };
+    for defn in &doc.definitions {
+        use q::OperationDefinition::*;
+        let set = match defn {
+            q::Definition::Operation(SelectionSet(set)) => set,
+            q::Definition::Operation(Query(query)) => &query.selection_set,
+            q::Definition::Operation(Mutation(mutation)) => &mutation.selection_set,
+            q::Definition::Operation(Subscription(subscription)) => &subscription.selection_set,
+            q::Definition::Fragment(_) => continue,
+        };
+        printer.walk(set, "");
+    }
+    printer.features
+}
+
+/// The Jaccard similarity of two fingerprints, between 0 and 1
+pub fn similarity(a: &BTreeSet<String>, b: &BTreeSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/// A group of similar query shapes of one subgraph
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    pub id: usize,
+    pub subgraph: String,
+    /// The query of the shape that spent the most time; all other members
+    /// are similar to it
+    pub query: String,
+    /// The `id` of the `QueryInfo` of each member
+    pub members: Vec<usize>,
+    pub calls: u64,
+    pub total_time: u64,
+    pub max_time: u64,
+    pub slow_count: u64,
+}
+
+impl Cluster {
+    pub fn avg(&self) -> f64 {
+        self.total_time as f64 / self.calls as f64
+    }
+}
+
+/// Put `infos` into clusters. Shapes are visited in order of total time,
+/// and each shape joins the first cluster of its subgraph whose first
+/// shape has a similarity of at least `threshold` with it, or starts a
+/// new cluster. Shapes whose query is truncated or can not be parsed are
+/// put into a cluster of their own
+pub fn cluster(infos: &[QueryInfo], threshold: f64) -> Vec<Cluster> {
+    let mut infos: Vec<&QueryInfo> = infos.iter().collect();
+    infos.sort_by(|a, b| b.total_time.cmp(&a.total_time).then(a.id.cmp(&b.id)));
+
+    let mut clusters: Vec<(Option<BTreeSet<String>>, Cluster)> = Vec::new();
+    for info in infos {
+        let print = if info.is_truncated() {
+            None
+        } else {
+            shape_hash::parse(&info.query).map(|doc| fingerprint(&doc, &info.max_variables))
+        };
+        let existing = clusters.iter_mut().find(|(leader, cluster)| {
+            cluster.subgraph == info.subgraph
+                && match (leader, &print) {
+                    (Some(leader), Some(print)) => similarity(leader, print) >= threshold,
+                    _ => false,
+                }
+        });
+        match existing {
+            Some((_, cluster)) => {
+                cluster.members.push(info.id);
+                cluster.calls += info.calls;
+                cluster.total_time += info.total_time;
+                cluster.max_time = cluster.max_time.max(info.max_time);
+                cluster.slow_count += info.slow_count;
+            }
+            None => {
+                let cluster = Cluster {
+                    id: clusters.len() + 1,
+                    subgraph: info.subgraph.clone(),
+                    query: info.query.clone(),
+                    members: vec![info.id],
+                    calls: info.calls,
+                    total_time: info.total_time,
+                    max_time: info.max_time,
+                    slow_count: info.slow_count,
+                };
+                clusters.push((print, cluster));
+            }
+        }
+    }
+    clusters.into_iter().map(|(_, cluster)| cluster).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::fixtures::info;
+
+    #[test]
+    fn near_duplicates() {
+        let mut infos = vec![
+            info(
+                1,
+                "QmSuBgRaPh",
+                "{ things(where: { a: 1 }) { id name owner { id name } } }",
+                1,
+                100,
+            ),
+            info(
+                2,
+                "QmSuBgRaPh",
+                "{ things(where: { a: 2 }) { id name owner { id name age } } }",
+                1,
+                300,
+            ),
+            info(3, "QmSuBgRaPh", "{ stuff(first: 5) { id } }", 1, 200),
+            info(
+                4,
+                "QmSuBgRaPh",
+                "query q($w: Thing_filter) { things(where: $w) { id name owner { id name } } }",
+                1,
+                50,
+            ),
+        ];
+        // The filter keys of Q4 come from its variables
+        infos[3].max_variables = "{\"w\":{\"a\":3}}".to_owned();
+
+        let clusters = cluster(&infos, DEFAULT_THRESHOLD);
+        assert_eq!(2, clusters.len());
+        assert_eq!(vec![2, 1, 4], clusters[0].members);
+        assert_eq!(450, clusters[0].total_time);
+        assert_eq!(vec![3], clusters[1].members);
+
+        // With a threshold of 1, only identical fingerprints are clustered
+        let clusters = cluster(&infos, 1.0);
+        let members: Vec<_> = clusters.iter().map(|c| c.members.clone()).collect();
+        assert_eq!(vec![vec![2], vec![3], vec![1, 4]], members);
+    }
+}
@@ -0,0 +1,311 @@
+//! Read and write summaries and entries in the Parquet format, which
+//! tools like DuckDB or pandas can scan much faster than JSONL. There is
+//! one column for each field of `QueryInfo` and `Entry`; fields that are
+//! maps or structs, like `groups`, are stored as JSON text
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::Field;
+use parquet::schema::parser::parse_message_type;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::{Entry, QueryInfo};
+
+/// The first bytes of every Parquet file
+pub const MAGIC: &[u8] = b"PAR1";
+
+/// The number of rows we write in one row group
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// Whether output to `filename` should be in Parquet format
+pub fn is_parquet(filename: &str) -> bool {
+    filename.ends_with(".parquet")
+}
+
+/// How to get the value of a column from a row
+enum Column<T> {
+    Str(&'static str, fn(&T) -> String),
+    OptStr(&'static str, fn(&T) -> Option<String>),
+    /// An optional column with JSON text
+    Json(&'static str, fn(&T) -> Option<String>),
+    Long(&'static str, fn(&T) -> u64),
+    Bool(&'static str, fn(&T) -> bool),
+}
+
+fn summary_columns() -> Vec<Column<QueryInfo>> {
+    use Column::*;
+
+    fn json<T: serde::Serialize>(value: &T) -> String {
+        serde_json::to_string(value).expect("maps and structs can be serialized")
+    }
+
+    vec![
+        Str("query", |info| info.query.clone()),
+        Str("subgraph", |info| info.subgraph.clone()),
+        Long("total_time", |info| info.total_time),
+        Long("time_squared", |info| info.time_squared),
+        Long("max_time", |info| info.max_time),
+        Str("max_uuid", |info| info.max_uuid.clone()),
+        Str("max_variables", |info| info.max_variables.clone()),
+        Long("max_complexity", |info| info.max_complexity),
+        Long("depth", |info| info.depth),
+        Long("fields", |info| info.fields),
+        Long("collections", |info| info.collections),
+        Long("slow_count", |info| info.slow_count),
+        Long("calls", |info| info.calls),
+        Long("id", |info| info.id as u64),
+        Long("hash", |info| info.hash),
+        Json("groups", |info| {
+            Some(&info.groups)
+                .filter(|groups| !groups.is_empty())
+                .map(json)
+        }),
+        Json("schema", |info| info.schema.as_ref().map(json)),
+        Long("error", |info| info.error),
+    ]
+}
+
+fn entry_columns() -> Vec<Column<Entry<'static>>> {
+    use Column::*;
+
+    vec![
+        Str("subgraph", |entry| entry.subgraph.to_string()),
+        Str("query_id", |entry| entry.query_id.to_string()),
+        Long("block", |entry| entry.block),
+        Long("time", |entry| entry.time),
+        Str("query", |entry| entry.query.to_string()),
+        Str("variables", |entry| entry.variables.to_string()),
+        OptStr("timestamp", |entry| {
+            entry.timestamp.as_ref().map(|ts| ts.to_string())
+        }),
+        Bool("truncated", |entry| entry.truncated),
+        OptStr("origin", |entry| {
+            entry.origin.as_ref().map(|o| o.to_string())
+        }),
+    ]
+}
+
+/// Start writing a Parquet file with `columns` to `out`
+fn file_writer<T, W: Write + Send>(
+    out: W,
+    name: &str,
+    columns: &[Column<T>],
+) -> Result<SerializedFileWriter<W>, ParquetError> {
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|column| match column {
+            Column::Str(name, _) => format!("REQUIRED BYTE_ARRAY {} (UTF8);", name),
+            Column::OptStr(name, _) => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", name),
+            Column::Json(name, _) => format!("OPTIONAL BYTE_ARRAY {} (JSON);", name),
+            Column::Long(name, _) => format!("REQUIRED INT64 {} (INTEGER(64,false));", name),
+            Column::Bool(name, _) => format!("REQUIRED BOOLEAN {};", name),
+        })
+        .collect();
+    let schema = parse_message_type(&format!("message {} {{ {} }}", name, fields.join(" ")))?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    SerializedFileWriter::new(out, Arc::new(schema), Arc::new(props))
+}
+
+/// Write `rows` as one row group
+fn write_row_group<T, W: Write + Send>(
+    writer: &mut SerializedFileWriter<W>,
+    columns: &[Column<T>],
+    rows: &[T],
+) -> Result<(), ParquetError> {
+    fn bytes(s: String) -> ByteArray {
+        ByteArray::from(s.into_bytes())
+    }
+
+    let mut group = writer.next_row_group()?;
+    for column in columns {
+        let mut writer = group
+            .next_column()?
+            .expect("the schema has a column for each of the columns");
+        match column {
+            Column::Str(_, get) => {
+                let values: Vec<_> = rows.iter().map(|row| bytes(get(row))).collect();
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, None, None)?;
+            }
+            Column::OptStr(_, get) | Column::Json(_, get) => {
+                let values: Vec<_> = rows.iter().map(get).collect();
+                let defs: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+                let values: Vec<_> = values.into_iter().flatten().map(bytes).collect();
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&defs), None)?;
+            }
+            Column::Long(_, get) => {
+                let values: Vec<_> = rows.iter().map(|row| get(row) as i64).collect();
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, None, None)?;
+            }
+            Column::Bool(_, get) => {
+                let values: Vec<_> = rows.iter().map(get).collect();
+                writer
+                    .typed::<BoolType>()
+                    .write_batch(&values, None, None)?;
+            }
+        }
+        writer.close()?;
+    }
+    group.close()?;
+    Ok(())
+}
+
+/// Write `infos` to `out` in Parquet format
+pub fn write_summaries<W: Write + Send>(out: W, infos: &[QueryInfo]) -> Result<(), io::Error> {
+    let columns = summary_columns();
+    let mut writer = file_writer(out, "query_info", &columns)?;
+    for chunk in infos.chunks(ROW_GROUP_SIZE) {
+        write_row_group(&mut writer, &columns, chunk)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// Read summaries that were written with `write_summaries`
+pub fn read_summaries(file: File) -> Result<Vec<QueryInfo>, io::Error> {
+    let columns = summary_columns();
+    let reader = SerializedFileReader::new(file)?;
+    let mut infos = Vec::new();
+    for row in reader.get_row_iter(None)? {
+        let mut obj = Map::new();
+        for (name, field) in row?.get_column_iter() {
+            let is_json = columns
+                .iter()
+                .any(|column| matches!(column, Column::Json(json, _) if json == name));
+            let value = match field {
+                Field::Null => continue,
+                Field::Str(s) if is_json => serde_json::from_str(s)?,
+                Field::Str(s) => Value::from(s.as_str()),
+                Field::ULong(n) => Value::from(*n),
+                Field::Long(n) => Value::from(*n as u64),
+                Field::Bool(b) => Value::from(*b),
+                _ => {
+                    let msg = format!("unexpected value in column {}: {}", name, field);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+            };
+            obj.insert(name.clone(), value);
+        }
+        infos.push(QueryInfo::upgrade(serde_json::from_value(Value::Object(
+            obj,
+        ))?));
+    }
+    Ok(infos)
+}
+
+/// Writes entries in Parquet format as they come in. Entries are
+/// buffered and written in row groups; the file is only complete once
+/// `close` has been called
+pub struct EntryWriter<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    columns: Vec<Column<Entry<'static>>>,
+    buffer: Vec<Entry<'static>>,
+}
+
+impl<W: Write + Send> EntryWriter<W> {
+    pub fn new(out: W) -> Result<Self, io::Error> {
+        let columns = entry_columns();
+        let writer = file_writer(out, "entry", &columns)?;
+        Ok(EntryWriter {
+            writer,
+            columns,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> Result<(), io::Error> {
+        fn owned(s: &str) -> Cow<'static, str> {
+            Cow::Owned(s.to_owned())
+        }
+
+        self.buffer.push(Entry {
+            subgraph: owned(&entry.subgraph),
+            query_id: owned(&entry.query_id),
+            block: entry.block,
+            time: entry.time,
+            query: owned(&entry.query),
+            variables: owned(&entry.variables),
+            timestamp: entry.timestamp.as_deref().map(owned),
+            truncated: entry.truncated,
+            origin: entry.origin.as_deref().map(owned),
+        });
+        if self.buffer.len() >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write all buffered entries as a row group
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        if !self.buffer.is_empty() {
+            write_row_group(&mut self.writer, &self.columns, &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    pub fn close(mut self) -> Result<(), io::Error> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchemaInfo;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn summaries_roundtrip() {
+        let mut grouped = QueryInfo::new(
+            "{ things { id } }".to_owned(),
+            "QmSuBgRaPh".to_owned(),
+            1,
+            u64::MAX - 5,
+            BTreeMap::from([("node".to_owned(), "index-1".to_owned())]),
+        );
+        grouped.calls = 3;
+        grouped.depth = 2;
+        grouped.fields = 2;
+        grouped.total_time = 1200;
+        grouped.max_variables = "{\"first\":10}".to_owned();
+        grouped.schema = Some(SchemaInfo {
+            lists: 1,
+            ..Default::default()
+        });
+        let mut plain = grouped.clone();
+        plain.id = 2;
+        plain.groups.clear();
+        plain.schema = None;
+
+        let mut buf = Vec::new();
+        write_summaries(&mut buf, &[grouped.clone(), plain.clone()]).unwrap();
+        assert!(buf.starts_with(MAGIC));
+        let path = std::env::temp_dir().join(format!("qlog-test-{}.parquet", std::process::id()));
+        std::fs::write(&path, &buf).unwrap();
+        let infos = read_summaries(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let json = |info: &QueryInfo| serde_json::to_string(info).unwrap();
+        assert_eq!(
+            vec![json(&grouped), json(&plain)],
+            infos.iter().map(json).collect::<Vec<_>>()
+        );
+    }
+}
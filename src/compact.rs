@@ -0,0 +1,216 @@
+//! A compact binary format for summaries that is much faster to read than
+//! JSONL. Summaries for the same query in different subgraphs or groups
+//! repeat the same long strings, and most slowest queries were run with
+//! the same variables; we therefore store every query, subgraph, group
+//! and variables string only once in a string table and refer to it by
+//! its index. The file starts with `MAGIC`, followed by the table and the
+//! summaries encoded with `bincode`
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+
+use crate::QueryInfo;
+
+/// The first bytes of every file in the compact format; the last byte is
+/// the version of the format
+pub const MAGIC: &[u8] = b"QLB\x01";
+
+/// Whether summaries written to `filename` should be in the compact format
+pub fn is_compact(filename: &str) -> bool {
+    filename.ends_with(".qlb")
+}
+
+/// A `QueryInfo` whose strings are indices into the string table
+#[derive(Serialize, Deserialize)]
+struct CompactInfo {
+    query: u32,
+    subgraph: u32,
+    total_time: u64,
+    time_squared: u64,
+    max_time: u64,
+    max_uuid: String,
+    max_variables: u32,
+    max_complexity: u64,
+    depth: u64,
+    fields: u64,
+    collections: u64,
+    slow_count: u64,
+    calls: u64,
+    id: u64,
+    hash: u64,
+    groups: Vec<(u32, u32)>,
+    /// `SchemaInfo` as JSON text; it skips empty fields when serialized,
+    /// which `bincode` can not handle
+    schema: Option<String>,
+    error: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Summaries {
+    strings: Vec<String>,
+    infos: Vec<CompactInfo>,
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Assigns each distinct string an index into `strings`
+#[derive(Default)]
+struct StringTable<'a> {
+    strings: Vec<String>,
+    index: HashMap<&'a str, u32>,
+}
+
+impl<'a> StringTable<'a> {
+    fn intern(&mut self, s: &'a str) -> u32 {
+        let strings = &mut self.strings;
+        *self.index.entry(s).or_insert_with(|| {
+            strings.push(s.to_owned());
+            strings.len() as u32 - 1
+        })
+    }
+}
+
+/// Write `infos` to `out` in the compact format
+pub fn write_summaries(out: &mut dyn Write, infos: &[QueryInfo]) -> Result<(), io::Error> {
+    let mut table = StringTable::default();
+    let infos: Vec<CompactInfo> = infos
+        .iter()
+        .map(|info| {
+            Ok(CompactInfo {
+                query: table.intern(&info.query),
+                subgraph: table.intern(&info.subgraph),
+                total_time: info.total_time,
+                time_squared: info.time_squared,
+                max_time: info.max_time,
+                max_uuid: info.max_uuid.clone(),
+                max_variables: table.intern(&info.max_variables),
+                max_complexity: info.max_complexity,
+                depth: info.depth,
+                fields: info.fields,
+                collections: info.collections,
+                slow_count: info.slow_count,
+                calls: info.calls,
+                id: info.id as u64,
+                hash: info.hash,
+                groups: info
+                    .groups
+                    .iter()
+                    .map(|(dim, value)| (table.intern(dim), table.intern(value)))
+                    .collect(),
+                schema: info
+                    .schema
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                error: info.error,
+            })
+        })
+        .collect::<Result<_, serde_json::Error>>()?;
+
+    let summaries = Summaries {
+        strings: table.strings,
+        infos,
+    };
+    out.write_all(MAGIC)?;
+    bincode::serialize_into(out, &summaries).map_err(invalid)
+}
+
+/// Read summaries that were written with `write_summaries`. The reader
+/// must be positioned at the start of the file
+pub fn read_summaries(mut input: impl Read) -> Result<Vec<QueryInfo>, io::Error> {
+    let mut magic = [0u8; 4];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("not a summary file in the compact format"));
+    }
+    let Summaries { strings, infos } = bincode::deserialize_from(input).map_err(invalid)?;
+
+    let string = |indx: u32| {
+        strings
+            .get(indx as usize)
+            .cloned()
+            .ok_or_else(|| invalid(format!("string {} is not in the string table", indx)))
+    };
+    infos
+        .into_iter()
+        .map(|info| {
+            let groups = info
+                .groups
+                .iter()
+                .map(|(dim, value)| Ok((string(*dim)?, string(*value)?)))
+                .collect::<Result<BTreeMap<_, _>, io::Error>>()?;
+            Ok(QueryInfo {
+                query: string(info.query)?,
+                subgraph: string(info.subgraph)?,
+                total_time: info.total_time,
+                time_squared: info.time_squared,
+                max_time: info.max_time,
+                max_uuid: info.max_uuid,
+                max_variables: string(info.max_variables)?,
+                max_complexity: info.max_complexity,
+                depth: info.depth,
+                fields: info.fields,
+                collections: info.collections,
+                slow_count: info.slow_count,
+                calls: info.calls,
+                id: info.id as usize,
+                hash: info.hash,
+                groups,
+                schema: info
+                    .schema
+                    .map(|schema| serde_json::from_str(&schema))
+                    .transpose()?,
+                error: info.error,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchemaInfo;
+
+    #[test]
+    fn compact_roundtrip() {
+        let mut grouped = QueryInfo::new(
+            "{ things { id } }".to_owned(),
+            "QmSuBgRaPh".to_owned(),
+            1,
+            u64::MAX - 5,
+            BTreeMap::from([("node".to_owned(), "index-1".to_owned())]),
+        );
+        grouped.calls = 3;
+        grouped.total_time = 1200;
+        grouped.max_variables = "{\"first\":10}".to_owned();
+        grouped.schema = Some(SchemaInfo {
+            lists: 1,
+            ..Default::default()
+        });
+        let mut other = grouped.clone();
+        other.id = 2;
+        other.groups = BTreeMap::from([("node".to_owned(), "index-2".to_owned())]);
+        other.schema = None;
+
+        let mut buf = Vec::new();
+        write_summaries(&mut buf, &[grouped.clone(), other.clone()]).unwrap();
+        assert!(buf.starts_with(MAGIC));
+        // The query is only stored once
+        let needle = grouped.query.as_bytes();
+        let count = buf
+            .windows(needle.len())
+            .filter(|window| *window == needle)
+            .count();
+        assert_eq!(1, count);
+
+        let infos = read_summaries(buf.as_slice()).unwrap();
+        let json = |info: &QueryInfo| serde_json::to_string(info).unwrap();
+        assert_eq!(
+            vec![json(&grouped), json(&other)],
+            infos.iter().map(json).collect::<Vec<_>>()
+        );
+        assert!(read_summaries(&b"{\"query\":1}"[..]).is_err());
+    }
+}
@@ -2,6 +2,8 @@
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 
+use crate::common::TRIMMED;
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Entry<'a> {
     pub subgraph: Cow<'a, str>,
@@ -12,6 +14,21 @@ pub struct Entry<'a> {
     pub variables: Cow<'a, str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timestamp: Option<Cow<'a, str>>,
+    /// The log line was trimmed before we saw it; `query` and `variables`
+    /// are incomplete or missing, but everything else is accurate
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub truncated: bool,
+    /// The graph-node instance that logged this entry, if we know it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Cow<'a, str>>,
+}
+
+/// The `subgraph` and `query_id` of truncated entries whose log line was
+/// cut off before them
+pub const UNKNOWN: &str = "(unknown)";
+
+fn is_false(b: &bool) -> bool {
+    !*b
 }
 
 // Return the part of the line between `prefix` and `suffix`, with
@@ -20,19 +37,26 @@ fn field<'a>(line: &'a str, prefix: &str, suffix: &str) -> Option<Cow<'a, str>>
     if let Some(start) = line.find(prefix) {
         if let Some(field) = line.get(start + prefix.len()..) {
             if let Some(end) = field.find(suffix) {
-                return field.get(..end).map(|s| Cow::from(s));
+                return field.get(..end).map(Cow::from);
             }
         }
     }
     None
 }
 
+// Return everything in `line` after `prefix`
+fn tail<'a>(line: &'a str, prefix: &str) -> Option<Cow<'a, str>> {
+    line.find(prefix)
+        .and_then(|start| line.get(start + prefix.len()..))
+        .map(Cow::from)
+}
+
 // Same as `field`, but we search for `suffix` from the right
 fn rfield<'a>(line: &'a str, prefix: &str, suffix: &str) -> Option<Cow<'a, str>> {
     if let Some(start) = line.find(prefix) {
         if let Some(field) = line.get(start + prefix.len()..) {
             if let Some(end) = field.rfind(suffix) {
-                return field.get(..end).map(|s| Cow::from(s));
+                return field.get(..end).map(Cow::from);
             }
         }
     }
@@ -40,6 +64,10 @@ fn rfield<'a>(line: &'a str, prefix: &str, suffix: &str) -> Option<Cow<'a, str>>
 }
 
 impl<'a> Entry<'a> {
+    /// Parse a line in the format graph-node uses for its text logs. A
+    /// line that `extract --text` marked as trimmed before the message
+    /// becomes a `truncated` entry, and an `origin` it appended after the
+    /// component becomes the `origin` of the entry
     pub fn parse(line: &'a str, timestamp: Option<&'a str>) -> Option<Entry<'a>> {
         let block = field(line, "block: ", ",");
         let time = field(line, "query_time_ms: ", ",");
@@ -71,7 +99,18 @@ impl<'a> Entry<'a> {
                 eprintln!("invalid query_time: {}", line);
                 0
             });
-            let timestamp = timestamp.map(|ts| Cow::from(ts));
+            let timestamp = timestamp.map(Cow::from);
+            let truncated = line
+                .find("block: ")
+                .is_some_and(|end| line[..end].contains(TRIMMED));
+            // `extract --text` appends the origin after the component
+            let origin = line
+                .rfind(", component: ")
+                .and_then(|start| {
+                    let origin = line[start..].find(", origin: ")?;
+                    Some(start + origin + ", origin: ".len())
+                })
+                .map(|start| Cow::from(line[start..].trim_end()));
 
             let entry = Entry {
                 subgraph,
@@ -81,12 +120,45 @@ impl<'a> Entry<'a> {
                 query,
                 variables,
                 timestamp,
+                truncated,
+                origin,
             };
             Some(entry)
         } else {
             None
         }
     }
+
+    /// Parse a line that was trimmed because it was too long. graph-node
+    /// logs the `block` and `query_time_ms` before the query, and the
+    /// `query_id` and `subgraph_id` after it; we only insist on the first
+    /// two since that is all that is left of most trimmed lines. Fields
+    /// that were cut off are set to `UNKNOWN`, and the query and variables
+    /// are taken as far as they are present in `line`. The resulting entry
+    /// is always marked as `truncated`
+    pub fn parse_trimmed(line: &'a str, timestamp: Option<&'a str>) -> Option<Entry<'a>> {
+        let block = field(line, "block: ", ",")?;
+        let time = field(line, "query_time_ms: ", ",")?;
+        let unknown = || Cow::from(UNKNOWN);
+        let subgraph = field(line, "subgraph_id: ", ", component: ").unwrap_or_else(unknown);
+        let query_id = field(line, "query_id: ", ",").unwrap_or_else(unknown);
+        let query = rfield(line, "query: ", " , query_id:")
+            .or_else(|| tail(line, "query: "))
+            .unwrap_or_default();
+        let variables = field(line, "variables: ", ", query: ").unwrap_or_else(|| "null".into());
+
+        Some(Entry {
+            subgraph,
+            query_id,
+            block: block.parse().ok()?,
+            time: time.parse().ok()?,
+            query,
+            variables,
+            timestamp: timestamp.map(Cow::from),
+            truncated: true,
+            origin: None,
+        })
+    }
 }
 
 pub trait EntryParser {
@@ -195,6 +267,8 @@ mod tests {
             variables: "null".into(),
             query_id: "f-1-4-b-e4".into(),
             timestamp: None,
+            truncated: false,
+            origin: None,
         };
         let entry = Entry::parse(LINE1, None);
         assert_eq!(Some(exp), entry);
@@ -207,6 +281,8 @@ mod tests {
             variables: "{}".into(),
             query_id: "f2-6b-48-b6-6b".into(),
             timestamp: None,
+            truncated: false,
+            origin: None,
         };
         let entry = Entry::parse(LINE2, None);
         assert_eq!(Some(exp), entry);
@@ -219,6 +295,8 @@ mod tests {
             variables: "null".into(),
             query_id: "c5-d3-4e-92-37".into(),
             timestamp: None,
+            truncated: false,
+            origin: None,
         };
         let entry = Entry::parse(LINE3, None);
         assert_eq!(Some(exp), entry);
@@ -231,6 +309,8 @@ mod tests {
             variables: "{\"id\":\"0xdeadbeef\"}".into(),
             query_id: "c8-1c-4c-98-65".into(),
             timestamp: None,
+            truncated: false,
+            origin: None,
         };
         let entry = Entry::parse(LINE4, None);
         assert_eq!(Some(exp), entry);
@@ -242,7 +322,9 @@ mod tests {
             query: "query TranscodersQuery($_v0_skip: Int, $_v1_first: Int, $_v2_where: Transcoder_filter) { transcoders(where: $_v2_where, skip: $_v0_skip, first: $_v1_first) { ...TranscoderFragment __typename } }  fragment TranscoderFragment on Transcoder { id active status lastRewardRound { id __typename } rewardCut feeShare pricePerSegment pendingRewardCut pendingFeeShare pendingPricePerSegment totalStake pools(orderBy: id, orderDirection: desc) { rewardTokens round { id __typename } __typename } __typename }".into(),
             variables: "{\"_v1_first\":100,\"_v2_where\":{\"status\":\"Registered\"},\"_v0_skip\":0}".into(),
             query_id: "2d-12-4b-a8-6b".into(),
-            timestamp: None
+            timestamp: None,
+            truncated: false,
+            origin: None,
         };
         let entry = Entry::parse(LINE5, None);
         assert_eq!(Some(exp), entry);
@@ -254,9 +336,61 @@ mod tests {
             query: "{ rateUpdates(orderBy: timestamp, orderDirection: desc, where: {synth: \"sEUR\", timestamp_gte: 1593123133, timestamp_lte: 1593209533}, first: 1000, skip: 0) { id synth rate block timestamp } }".into(),
             variables: "null".into(),
             query_id: "cb9af68f-ae60-4dba-b9b3-89aee6fe8eca".into(),
-            timestamp: None
+            timestamp: None,
+            truncated: false,
+            origin: None,
         };
         let entry = Entry::parse(LINE6, None);
         assert_eq!(Some(exp), entry);
     }
+
+    #[test]
+    fn test_trimmed_query() {
+        const LINE: &str = "Dec 31 22:59:58.863 INFO Query timing (GraphQL), \
+                            block: 1234, \
+                            query_time_ms: 2657, \
+                            variables: {\"_v1_first\":100}, \
+                            query: query TranscodersQuery($_v1_first: Int) { transcoders(first: $_v1_first) { id } } , \
+                            query_id: 2d-12-4b-a8-6b, \
+                            subgraph_id: QmSuBgRaPh, \
+                            component: GraphQlRunner";
+        // StackDriver cuts the line in the middle of the query, and with
+        // it the query_id and subgraph_id
+        let cut = LINE.find("transcoders(").unwrap() + 20;
+        let trimmed = format!("[Trimmed] {}", &LINE[..cut]);
+
+        let exp = Entry {
+            subgraph: UNKNOWN.into(),
+            block: 1234,
+            time: 2657,
+            query: "query TranscodersQuery($_v1_first: Int) { transcoders(first: $".into(),
+            variables: "{\"_v1_first\":100}".into(),
+            query_id: UNKNOWN.into(),
+            timestamp: Some("2020-12-31T22:59:58.863Z".into()),
+            truncated: true,
+            origin: None,
+        };
+        let entry = Entry::parse_trimmed(&trimmed, Some("2020-12-31T22:59:58.863Z"));
+        assert_eq!(Some(exp), entry);
+
+        // Cut in the middle of the variables
+        let cut = LINE.find("_v1_first").unwrap();
+        let entry = Entry::parse_trimmed(&LINE[..cut], None).unwrap();
+        assert_eq!((1234, 2657), (entry.block, entry.time));
+        assert_eq!(
+            ("", "null"),
+            (entry.query.as_ref(), entry.variables.as_ref())
+        );
+
+        // Trimmed only in the component; everything is still there
+        let cut = LINE.find("GraphQlRunner").unwrap();
+        let entry = Entry::parse_trimmed(&LINE[..cut], None).unwrap();
+        assert_eq!("QmSuBgRaPh", entry.subgraph);
+        assert_eq!("2d-12-4b-a8-6b", entry.query_id);
+        assert!(entry.query.ends_with("{ id } }"));
+
+        // Without the query time, the entry is useless
+        let cut = LINE.find("query_time_ms").unwrap();
+        assert_eq!(None, Entry::parse_trimmed(&LINE[..cut], None));
+    }
 }
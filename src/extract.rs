@@ -1,14 +1,40 @@
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, Read, Write};
+use std::str::FromStr;
 use walkdir::WalkDir;
 
 use crate::common::{GQL_MARKER, TRIMMED};
 use crate::Entry;
 
+/// Where the extracted log lines go. The `origin` passed to the methods
+/// identifies the graph-node instance that produced a log line, if we
+/// know it
 pub trait ExtractWriter {
-    fn write(&mut self, text: &str, timestamp: Option<&str>) -> io::Result<usize>;
+    fn write(
+        &mut self,
+        text: &str,
+        timestamp: Option<&str>,
+        origin: Option<&str>,
+    ) -> io::Result<usize>;
+
+    /// Write what we can recover from a log line that was trimmed. By
+    /// default, trimmed lines are skipped
+    fn write_trimmed(
+        &mut self,
+        _text: &str,
+        _timestamp: Option<&str>,
+        _origin: Option<&str>,
+    ) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    /// Write an entry that was logged with structured fields rather than
+    /// as a line of text
+    fn write_entry(&mut self, entry: &Entry) -> io::Result<usize>;
 }
 
 pub struct TextWriter<T> {
@@ -21,16 +47,80 @@ impl<T> TextWriter<T> {
     }
 }
 
+/// Append `origin` to the log line `text` the way `Entry::parse` expects
+/// it, i.e., after the component
+fn with_origin<'a>(text: &'a str, origin: Option<&str>) -> Cow<'a, str> {
+    match origin {
+        Some(origin) => {
+            let line = text.trim_end_matches('\n');
+            Cow::from(format!(
+                "{}, origin: {}{}",
+                line,
+                origin,
+                &text[line.len()..]
+            ))
+        }
+        None => Cow::from(text),
+    }
+}
+
 impl<T: Write> ExtractWriter for TextWriter<T> {
-    fn write(&mut self, text: &str, timestamp: Option<&str>) -> io::Result<usize> {
+    fn write(
+        &mut self,
+        text: &str,
+        timestamp: Option<&str>,
+        origin: Option<&str>,
+    ) -> io::Result<usize> {
+        let text = with_origin(text, origin);
         if let (Some(ts), Some(start)) = (timestamp, text.find(" INFO ")) {
             let mut count = self.out.write(ts.as_bytes())?;
-            count += self.out.write(text[start..].as_bytes())?;
+            count += self.out.write(&text.as_bytes()[start..])?;
             Ok(count)
         } else {
             self.out.write(text.as_bytes())
         }
     }
+
+    fn write_trimmed(
+        &mut self,
+        text: &str,
+        timestamp: Option<&str>,
+        origin: Option<&str>,
+    ) -> io::Result<usize> {
+        match Entry::parse_trimmed(text, timestamp) {
+            Some(mut entry) => {
+                entry.origin = origin.map(Cow::from);
+                self.write_entry(&entry)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn write_entry(&mut self, entry: &Entry) -> io::Result<usize> {
+        // Produce the same format that graph-node uses for its text logs;
+        // truncated entries are marked and the origin is appended so that
+        // `Entry::parse` knows about them
+        let marker = if entry.truncated {
+            format!("{} ", TRIMMED)
+        } else {
+            String::new()
+        };
+        let line = format!(
+            "{} INFO {}{}, block: {}, query_time_ms: {}, variables: {}, query: {} , \
+             query_id: {}, subgraph_id: {}, component: GraphQlRunner\n",
+            entry.timestamp.as_deref().unwrap_or("-"),
+            marker,
+            GQL_MARKER,
+            entry.block,
+            entry.time,
+            entry.variables,
+            entry.query,
+            entry.query_id,
+            entry.subgraph
+        );
+        let line = with_origin(&line, entry.origin.as_deref());
+        self.out.write(line.as_bytes())
+    }
 }
 
 pub struct JsonlWriter<T> {
@@ -45,11 +135,15 @@ impl<T> JsonlWriter<T> {
 }
 
 impl<T: Write> ExtractWriter for JsonlWriter<T> {
-    fn write(&mut self, text: &str, timestamp: Option<&str>) -> io::Result<usize> {
-        if let Some(entry) = Entry::parse(text, timestamp) {
-            let json = serde_json::to_string(&entry)?;
-            write!(self.out, "{}\n", json)?;
-            Ok(json.len() + 1)
+    fn write(
+        &mut self,
+        text: &str,
+        timestamp: Option<&str>,
+        origin: Option<&str>,
+    ) -> io::Result<usize> {
+        if let Some(mut entry) = Entry::parse(text, timestamp) {
+            entry.origin = origin.map(Cow::from);
+            self.write_entry(&entry)
         } else {
             if self.print_extra {
                 eprintln!("not a query: {}", text);
@@ -57,78 +151,719 @@ impl<T: Write> ExtractWriter for JsonlWriter<T> {
             Ok(0)
         }
     }
+
+    fn write_trimmed(
+        &mut self,
+        text: &str,
+        timestamp: Option<&str>,
+        origin: Option<&str>,
+    ) -> io::Result<usize> {
+        if let Some(mut entry) = Entry::parse_trimmed(text, timestamp) {
+            entry.origin = origin.map(Cow::from);
+            self.write_entry(&entry)
+        } else {
+            if self.print_extra {
+                eprintln!("not a recoverable query: {}", text);
+            }
+            Ok(0)
+        }
+    }
+
+    fn write_entry(&mut self, entry: &Entry) -> io::Result<usize> {
+        let json = serde_json::to_string(entry)?;
+        writeln!(self.out, "{}", json)?;
+        Ok(json.len() + 1)
+    }
+}
+
+/// Counts of what we saw while extracting
+#[derive(Default)]
+struct Counts {
+    /// The number of lines we read
+    lines: usize,
+    /// The number of lines that StackDriver trimmed
+    trimmed: usize,
+    /// The number of trimmed lines from which we could still recover
+    /// an entry
+    recovered: usize,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.lines += other.lines;
+        self.trimmed += other.trimmed;
+        self.recovered += other.recovered;
+    }
+}
+
+/// The parts of a log entry that StackDriver split into several entries
+/// because it was too large. All parts carry the same `split.uid`
+struct Split {
+    timestamp: Option<String>,
+    origin: Option<String>,
+    parts: Vec<Option<String>>,
+}
+
+impl Split {
+    fn new(total: usize, timestamp: Option<&str>, origin: Option<&str>) -> Self {
+        Split {
+            timestamp: timestamp.map(|ts| ts.to_owned()),
+            origin: origin.map(|origin| origin.to_owned()),
+            parts: vec![None; total],
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.parts.iter().all(|part| part.is_some())
+    }
+
+    /// The text of all the parts we have, up to the first missing one
+    fn text(&self) -> String {
+        self.parts
+            .iter()
+            .map_while(|part| part.as_deref())
+            .collect()
+    }
+}
+
+/// Extract `(uid, index, totalSplits)` from the `split` metadata of a
+/// StackDriver entry
+fn split_info(map: &Map<String, Value>) -> Option<(&str, usize, usize)> {
+    let split = map.get("split")?;
+    let uid = split.get("uid")?.as_str()?;
+    let index = split.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+    let total = split.get("totalSplits")?.as_u64()?;
+    if index < total {
+        Some((uid, index as usize, total as usize))
+    } else {
+        None
+    }
+}
+
+/// Build the origin of an entry by joining the values of `labels` with a
+/// `/`. Each label is looked up in `sources` in order, and the first value
+/// we find is used
+fn origin(sources: &[Option<&Map<String, Value>>], labels: &[String]) -> Option<String> {
+    let values: Vec<&str> = labels
+        .iter()
+        .filter_map(|label| {
+            sources
+                .iter()
+                .flatten()
+                .find_map(|source| source.get(label))
+                .and_then(|v| v.as_str())
+        })
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join("/"))
+    }
+}
+
+/// The text of the log message in a StackDriver entry. That is either the
+/// `textPayload` or the message in a `jsonPayload`
+fn payload_text(map: &Map<String, Value>) -> Option<&str> {
+    match map.get("textPayload") {
+        Some(Value::String(text)) => Some(text),
+        _ => map
+            .get("jsonPayload")
+            .and_then(|p| p.get("message").or_else(|| p.get("msg")))
+            .and_then(|v| v.as_str()),
+    }
+}
+
+/// Turn a `jsonPayload` in which graph-node logged the query timing as
+/// individual fields into an `Entry`
+fn structured_entry<'a>(
+    payload: &'a Value,
+    timestamp: Option<&'a str>,
+    origin: Option<&'a str>,
+) -> Option<Entry<'a>> {
+    fn number(value: &Value) -> Option<u64> {
+        match value {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn text(value: &Value) -> Cow<'_, str> {
+        match value {
+            Value::String(s) => Cow::from(s.as_str()),
+            value => Cow::from(value.to_string()),
+        }
+    }
+
+    let time = number(payload.get("query_time_ms")?)?;
+    Some(Entry {
+        subgraph: text(payload.get("subgraph_id")?),
+        query_id: text(payload.get("query_id")?),
+        block: payload.get("block").and_then(number).unwrap_or(0),
+        time,
+        query: text(payload.get("query")?),
+        variables: payload
+            .get("variables")
+            .map(text)
+            .unwrap_or_else(|| Cow::from("null")),
+        timestamp: timestamp.map(Cow::from),
+        truncated: false,
+        origin: origin.map(Cow::from),
+    })
+}
+
+fn write_text(
+    text: &str,
+    timestamp: Option<&str>,
+    origin: Option<&str>,
+    out: &mut dyn ExtractWriter,
+    counts: &mut Counts,
+) -> io::Result<usize> {
+    if text.contains(TRIMMED) {
+        write_trimmed(text, timestamp, origin, out, counts)
+    } else if text.contains(GQL_MARKER) {
+        out.write(text, timestamp, origin)
+    } else {
+        io::stderr().write(text.as_bytes())
+    }
+}
+
+fn write_trimmed(
+    text: &str,
+    timestamp: Option<&str>,
+    origin: Option<&str>,
+    out: &mut dyn ExtractWriter,
+    counts: &mut Counts,
+) -> io::Result<usize> {
+    counts.trimmed += 1;
+    if !text.contains(GQL_MARKER) {
+        return Ok(0);
+    }
+    let count = out.write_trimmed(text, timestamp, origin)?;
+    if count > 0 {
+        counts.recovered += 1;
+    }
+    Ok(count)
+}
+
+/// The formats of log exports that `extract` can read. All of them have
+/// one JSON object per line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Google Cloud's StackDriver, with the log line in `textPayload` or
+    /// `jsonPayload`
+    StackDriver,
+    /// AWS CloudWatch, with the log line in `message` and a `timestamp`
+    /// in milliseconds since the epoch
+    CloudWatch,
+    /// Grafana Loki as produced by `logcli query --output=jsonl`, with
+    /// the log line in `line`
+    Loki,
+}
+
+impl Format {
+    /// The labels that identify the graph-node instance if the user does
+    /// not tell us otherwise
+    pub fn default_labels(&self) -> &'static str {
+        match self {
+            Format::StackDriver => "pod_name",
+            Format::CloudWatch => "logStreamName",
+            Format::Loki => "pod",
+        }
+    }
+
+    /// The extensions of files we read when reading a directory
+    fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Format::StackDriver | Format::CloudWatch => &["json"],
+            Format::Loki => &["json", "jsonl"],
+        }
+    }
+
+    fn reader<'a>(&self, labels: &'a [String]) -> Box<dyn SourceReader + 'a> {
+        match self {
+            Format::StackDriver => Box::new(StackDriverReader::new(labels)),
+            Format::CloudWatch => Box::new(CloudWatchReader { labels }),
+            Format::Loki => Box::new(LokiReader { labels }),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stackdriver" => Ok(Format::StackDriver),
+            "cloudwatch" => Ok(Format::CloudWatch),
+            "loki" => Ok(Format::Loki),
+            _ => Err(format!(
+                "unknown format `{}`; must be one of stackdriver, cloudwatch, or loki",
+                s
+            )),
+        }
+    }
+}
+
+/// Reads the records of one export format and passes the log lines they
+/// contain to an `ExtractWriter`
+trait SourceReader {
+    fn read(
+        &mut self,
+        record: &Map<String, Value>,
+        out: &mut dyn ExtractWriter,
+        counts: &mut Counts,
+    ) -> io::Result<usize>;
+
+    /// Called once all records from all sources have been read
+    fn finish(&mut self, _out: &mut dyn ExtractWriter, _counts: &mut Counts) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct StackDriverReader<'a> {
+    labels: &'a [String],
+    splits: HashMap<String, Split>,
+}
+
+impl<'a> StackDriverReader<'a> {
+    fn new(labels: &'a [String]) -> Self {
+        StackDriverReader {
+            labels,
+            splits: HashMap::new(),
+        }
+    }
+}
+
+impl SourceReader for StackDriverReader<'_> {
+    fn read(
+        &mut self,
+        map: &Map<String, Value>,
+        out: &mut dyn ExtractWriter,
+        counts: &mut Counts,
+    ) -> io::Result<usize> {
+        let ts = map.get("timestamp").and_then(|v| v.as_str());
+        let resource_labels = map.get("resource").and_then(|r| r.get("labels"));
+        let labels = map.get("labels");
+        let origin = origin(
+            &[
+                resource_labels.and_then(Value::as_object),
+                labels.and_then(Value::as_object),
+            ],
+            self.labels,
+        );
+        let origin = origin.as_deref();
+        let structured = map
+            .get("jsonPayload")
+            .filter(|payload| payload.get("query_time_ms").is_some());
+
+        if let Some(payload) = structured {
+            match structured_entry(payload, ts, origin) {
+                Some(entry) => out.write_entry(&entry),
+                None => Ok(0),
+            }
+        } else if let Some(text) = payload_text(map) {
+            if let Some((uid, index, total)) = split_info(map) {
+                let split = self
+                    .splits
+                    .entry(uid.to_owned())
+                    .or_insert_with(|| Split::new(total, ts, origin));
+                if let Some(part) = split.parts.get_mut(index) {
+                    *part = Some(text.to_owned());
+                }
+                if split.is_complete() {
+                    let split = self.splits.remove(uid).unwrap();
+                    write_text(
+                        &split.text(),
+                        split.timestamp.as_deref(),
+                        split.origin.as_deref(),
+                        out,
+                        counts,
+                    )
+                } else {
+                    Ok(0)
+                }
+            } else {
+                write_text(text, ts, origin, out, counts)
+            }
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn finish(&mut self, out: &mut dyn ExtractWriter, counts: &mut Counts) -> io::Result<()> {
+        // Entries for which we did not get all the parts are treated as if
+        // StackDriver had trimmed them
+        for (_, split) in self.splits.drain() {
+            write_trimmed(
+                &split.text(),
+                split.timestamp.as_deref(),
+                split.origin.as_deref(),
+                out,
+                counts,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct CloudWatchReader<'a> {
+    labels: &'a [String],
+}
+
+impl SourceReader for CloudWatchReader<'_> {
+    fn read(
+        &mut self,
+        map: &Map<String, Value>,
+        out: &mut dyn ExtractWriter,
+        counts: &mut Counts,
+    ) -> io::Result<usize> {
+        let text = match map.get("message").and_then(|v| v.as_str()) {
+            Some(text) => text,
+            None => return Ok(0),
+        };
+        let ts = map
+            .get("timestamp")
+            .and_then(|v| v.as_u64())
+            .map(rfc3339_from_millis);
+        // CloudWatch has no labels; the `logStreamName` and friends are
+        // fields of the record itself
+        let origin = origin(&[Some(map)], self.labels);
+        write_text(text, ts.as_deref(), origin.as_deref(), out, counts)
+    }
+}
+
+struct LokiReader<'a> {
+    labels: &'a [String],
+}
+
+impl SourceReader for LokiReader<'_> {
+    fn read(
+        &mut self,
+        map: &Map<String, Value>,
+        out: &mut dyn ExtractWriter,
+        counts: &mut Counts,
+    ) -> io::Result<usize> {
+        let text = match map.get("line").and_then(|v| v.as_str()) {
+            Some(text) => text,
+            None => return Ok(0),
+        };
+        let ts = map.get("timestamp").and_then(|v| v.as_str());
+        let origin = origin(&[map.get("labels").and_then(Value::as_object)], self.labels);
+        write_text(text, ts, origin.as_deref(), out, counts)
+    }
+}
+
+/// Format `millis` since the Unix epoch as an RFC 3339 timestamp in UTC,
+/// which is what StackDriver and Loki use
+pub(crate) fn rfc3339_from_millis(millis: u64) -> String {
+    let secs = millis / 1000;
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Convert days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        millis % 1000
+    )
 }
 
 fn extract<T: Read>(
     source: T,
     out: &mut dyn ExtractWriter,
-) -> Result<(usize, usize), std::io::Error> {
-    let mut count: usize = 0;
-    let mut trimmed_count: usize = 0;
-    let mut stderr = io::stderr();
+    reader: &mut dyn SourceReader,
+) -> Result<Counts, std::io::Error> {
+    let mut counts = Counts::default();
 
-    let reader = BufReader::new(source);
+    let lines = BufReader::new(source);
 
     // Going line by line is much faster than using
     // serde_json::Deserializer::from_reader(reader).into_iter();
-    for line in reader.lines() {
-        count += 1;
+    for line in lines.lines() {
+        counts.lines += 1;
         if let Value::Object(map) = serde_json::from_str(&line?)? {
-            if let Some(Value::String(text)) = map.get("textPayload") {
-                let res = if text.contains(TRIMMED) {
-                    trimmed_count += 1;
-                    Ok(0)
-                } else if text.contains(GQL_MARKER) {
-                    let ts = map.get("timestamp").and_then(|v| v.as_str());
-                    out.write(text, ts)
+            if let Err(e) = reader.read(&map, out, &mut counts) {
+                if e.kind() == std::io::ErrorKind::BrokenPipe {
+                    return Ok(counts);
                 } else {
-                    stderr.write(text.as_bytes())
-                };
-                if let Err(e) = res {
-                    if e.kind() == std::io::ErrorKind::BrokenPipe {
-                        return Ok((count, trimmed_count));
-                    } else {
-                        return Err(e);
-                    }
+                    return Err(e);
                 }
             }
         }
     }
 
-    Ok((count, trimmed_count))
+    Ok(counts)
 }
 
-/// The 'extract' subcommand turning a StackDriver logfile into a plain
-/// textual logfile by pulling out the 'textPayload' for each entry
-pub fn run(dir: &str, out: &mut dyn ExtractWriter, verbose: bool) -> Result<(), std::io::Error> {
-    let json_ext = OsStr::new("json");
-    let mut trimmed_count: usize = 0;
-    let mut count: usize = 0;
+/// The 'extract' subcommand turning a log export in `format` into a plain
+/// textual logfile by pulling out the log line for each entry, e.g., the
+/// 'textPayload' or 'jsonPayload' for StackDriver. The values of `labels`
+/// are recorded as the origin of each entry. StackDriver can split an
+/// entry across two files, so all files are read with the same reader
+pub fn run(
+    dir: &str,
+    out: &mut dyn ExtractWriter,
+    format: Format,
+    labels: &[String],
+    verbose: bool,
+) -> Result<(), std::io::Error> {
+    let mut counts = Counts::default();
+    let mut reader = format.reader(labels);
 
     if dir == "-" {
         let stdin = io::stdin();
-        let (cur_count, cur_trimmed_count) = extract(stdin, out)?;
-        count += cur_count;
-        trimmed_count += cur_trimmed_count;
+        counts.add(&extract(stdin, out, reader.as_mut())?);
     } else {
         for entry in WalkDir::new(dir) {
             let entry = entry?;
 
-            if entry.file_type().is_file() && entry.path().extension() == Some(&json_ext) {
+            let ext = entry.path().extension().and_then(OsStr::to_str);
+            if entry.file_type().is_file()
+                && ext.is_some_and(|ext| format.extensions().contains(&ext))
+            {
                 if verbose {
                     eprintln!("Reading {}", entry.path().to_string_lossy());
                 }
                 let file = File::open(entry.path())?;
 
-                let (cur_count, cur_trimmed_count) = extract(file, out)?;
-                count += cur_count;
-                trimmed_count += cur_trimmed_count;
+                counts.add(&extract(file, out, reader.as_mut())?);
             }
         }
     }
+    if let Err(e) = reader.finish(out, &mut counts) {
+        if e.kind() != std::io::ErrorKind::BrokenPipe {
+            return Err(e);
+        }
+    }
     eprintln!(
-        "Skipped {} trimmed lines out of {} lines",
-        trimmed_count, count
+        "Found {} trimmed lines out of {} lines and recovered {} of them",
+        counts.trimmed, counts.lines, counts.recovered
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = "Dec 31 23:59:59.739 INFO Query timing (GraphQL), \
+                        block: 10344025, \
+                        query_time_ms: 14, \
+                        variables: null, \
+                        query: query TranscoderQuery { transcoders(first: 1) { id } } , \
+                        query_id: c5-d3-4e-92-37, \
+                        subgraph_id: QmeYBGccAwahY, \
+                        component: GraphQlRunner";
+
+    fn stackdriver(text: &str, split: Option<(usize, usize)>) -> String {
+        let mut entry = serde_json::json!({
+            "textPayload": text,
+            "timestamp": "2020-12-31T23:59:59.739Z",
+        });
+        if let Some((index, total)) = split {
+            entry["split"] = serde_json::json!({
+                "uid": "a1b2c3",
+                "index": index,
+                "totalSplits": total,
+            });
+        }
+        entry.to_string()
+    }
+
+    /// Extract the entries from `sources`, each of which stands for one
+    /// export file
+    fn run_extract(sources: Vec<Vec<String>>) -> (Vec<Entry<'static>>, Counts) {
+        let mut out = JsonlWriter::new(Vec::new(), false);
+        let labels = vec!["pod_name".to_owned()];
+        let mut reader = StackDriverReader::new(&labels);
+        let mut counts = Counts::default();
+        for lines in sources {
+            let input = lines.join("\n");
+            counts
+                .add(&extract(input.as_bytes(), &mut out, &mut reader).expect("extract succeeds"));
+        }
+        reader.finish(&mut out, &mut counts).unwrap();
+        let entries = String::from_utf8(out.out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        (entries, counts)
+    }
+
+    #[test]
+    fn reassembles_splits() {
+        let (head, rest) = LINE.split_at(60);
+        let (middle, end) = rest.split_at(70);
+        // Parts can arrive out of order
+        let lines = vec![
+            stackdriver(end, Some((2, 3))),
+            stackdriver(head, Some((0, 3))),
+            stackdriver(middle, Some((1, 3))),
+        ];
+        let (entries, counts) = run_extract(vec![lines]);
+        assert_eq!(1, entries.len());
+        assert_eq!(
+            Entry::parse(LINE, Some("2020-12-31T23:59:59.739Z")).as_ref(),
+            entries.first()
+        );
+        assert_eq!(3, counts.lines);
+        assert_eq!(0, counts.trimmed);
+
+        // The parts of an entry can end up in different files
+        let (entries, counts) = run_extract(vec![
+            vec![stackdriver(head, Some((0, 3)))],
+            vec![
+                stackdriver(middle, Some((1, 3))),
+                stackdriver(end, Some((2, 3))),
+            ],
+        ]);
+        assert_eq!(1, entries.len());
+        assert_eq!(0, counts.trimmed);
+    }
+
+    #[test]
+    fn recovers_trimmed() {
+        let cut = LINE.find("transcoders(").unwrap();
+        let trimmed = format!("[Trimmed] {}", &LINE[..cut]);
+        let lines = vec![
+            stackdriver(&trimmed, None),
+            // An incomplete split is treated like a trimmed line
+            stackdriver(&LINE[..40], Some((0, 2))),
+        ];
+        let (entries, counts) = run_extract(vec![lines]);
+        assert_eq!(1, entries.len());
+        assert!(entries[0].truncated);
+        assert_eq!(14, entries[0].time);
+        assert_eq!(crate::entry::UNKNOWN, entries[0].subgraph);
+        assert_eq!("query TranscoderQuery { ", entries[0].query);
+        assert_eq!(2, counts.trimmed);
+        assert_eq!(1, counts.recovered);
+    }
+
+    #[test]
+    fn text_keeps_trimmed() {
+        let cut = LINE.find("transcoders(").unwrap();
+        let trimmed = format!("[Trimmed] {}", &LINE[..cut]);
+        let mut out = TextWriter::new(Vec::new());
+        let labels = vec!["pod_name".to_owned()];
+        let mut reader = StackDriverReader::new(&labels);
+        let input = stackdriver(&trimmed, None);
+        let counts = extract(input.as_bytes(), &mut out, &mut reader).unwrap();
+        assert_eq!(1, counts.recovered);
+
+        let text = String::from_utf8(out.out).unwrap();
+        let entry = Entry::parse(&text, None).unwrap();
+        assert!(entry.truncated);
+        assert_eq!(14, entry.time);
+        assert_eq!(crate::entry::UNKNOWN, entry.subgraph);
+        assert_eq!("query TranscoderQuery { ", entry.query);
+        assert!(!Entry::parse(LINE, None).unwrap().truncated);
+    }
+
+    #[test]
+    fn text_keeps_origin() {
+        let message = serde_json::json!({
+            "textPayload": format!("{}\n", LINE),
+            "resource": { "labels": { "pod_name": "query-node-1" } },
+        });
+        let mut out = TextWriter::new(Vec::new());
+        let labels = vec!["pod_name".to_owned()];
+        let mut reader = StackDriverReader::new(&labels);
+        let input = message.to_string();
+        extract(input.as_bytes(), &mut out, &mut reader).unwrap();
+
+        let text = String::from_utf8(out.out).unwrap();
+        assert!(text.ends_with(", origin: query-node-1\n"));
+        let entry = Entry::parse(&text, None).unwrap();
+        assert_eq!("QmeYBGccAwahY", entry.subgraph);
+        assert_eq!(Some("query-node-1"), entry.origin.as_deref());
+        assert_eq!(None, Entry::parse(LINE, None).unwrap().origin);
+    }
+
+    #[test]
+    fn json_payload_and_origin() {
+        let structured = serde_json::json!({
+            "jsonPayload": {
+                "msg": "Query timing (GraphQL)",
+                "block": 10344025,
+                "query_time_ms": "14",
+                "variables": {"id": "0xdeadbeef"},
+                "query": "query exchange($id: String!) { exchange(id: $id) { id } }",
+                "query_id": "c8-1c-4c-98-65",
+                "subgraph_id": "QmSuBgRaPh",
+            },
+            "resource": { "labels": { "pod_name": "query-node-1" } },
+            "timestamp": "2020-12-31T23:59:59.846Z",
+        });
+        let message = serde_json::json!({
+            "jsonPayload": { "message": LINE },
+            "labels": { "pod_name": "query-node-2" },
+        });
+        let (entries, _) = run_extract(vec![vec![structured.to_string(), message.to_string()]]);
+        assert_eq!(2, entries.len());
+
+        assert_eq!(14, entries[0].time);
+        assert_eq!("{\"id\":\"0xdeadbeef\"}", entries[0].variables);
+        assert_eq!(Some("query-node-1"), entries[0].origin.as_deref());
+
+        assert_eq!("QmeYBGccAwahY", entries[1].subgraph);
+        assert_eq!(Some("query-node-2"), entries[1].origin.as_deref());
+    }
+
+    #[test]
+    fn cloudwatch_and_loki() {
+        let labels = vec!["logStreamName".to_owned()];
+        let cloudwatch = serde_json::json!({
+            "timestamp": 1609459199739u64,
+            "message": LINE,
+            "logStreamName": "graph-node/query-node-1",
+        });
+        let mut out = JsonlWriter::new(Vec::new(), false);
+        let mut reader = CloudWatchReader { labels: &labels };
+        extract(cloudwatch.to_string().as_bytes(), &mut out, &mut reader).unwrap();
+        let entry: Entry = serde_json::from_slice(&out.out).unwrap();
+        assert_eq!(Some("2020-12-31T23:59:59.739Z"), entry.timestamp.as_deref());
+        assert_eq!(Some("graph-node/query-node-1"), entry.origin.as_deref());
+
+        let labels = vec!["pod".to_owned()];
+        let loki = serde_json::json!({
+            "labels": { "app": "graph-node", "pod": "query-node-2" },
+            "line": LINE,
+            "timestamp": "2020-12-31T23:59:59.739Z",
+        });
+        let mut out = JsonlWriter::new(Vec::new(), false);
+        let mut reader = LokiReader { labels: &labels };
+        extract(loki.to_string().as_bytes(), &mut out, &mut reader).unwrap();
+        let entry: Entry = serde_json::from_slice(&out.out).unwrap();
+        assert_eq!(14, entry.time);
+        assert_eq!(Some("2020-12-31T23:59:59.739Z"), entry.timestamp.as_deref());
+        assert_eq!(Some("query-node-2"), entry.origin.as_deref());
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!("1970-01-01T00:00:00.000Z", rfc3339_from_millis(0));
+        assert_eq!(
+            "2000-02-29T12:34:56.789Z",
+            rfc3339_from_millis(951827696789)
+        );
+    }
+}
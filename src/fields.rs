@@ -0,0 +1,331 @@
+//! Attribute the time spent on queries to the top-level fields of the
+//! queries, e.g., `things` in `{ things(where: { ... }) { id } }`. Since
+//! each top-level field of a query usually turns into its own database
+//! query, this shows which entity types are expensive across all shapes.
+//! With the schemas of the subgraphs, we also know the entity type each
+//! field returns and can add up the time for each entity type
+use graphql_parser::query as q;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::shape_hash;
+use crate::{QueryInfo, Schemas};
+
+/// The entity type for fields we could not find in the schema
+pub const UNKNOWN_ENTITY: &str = "(unknown)";
+
+/// The top-level fields of all operations in `doc`. Fragments that are
+/// spread into the top-level selection are followed, and `__typename` is
+/// ignored
+pub fn root_selections(doc: &q::Document) -> Vec<&q::Field> {
+    selections(doc, false)
+}
+
+/// Like `root_selections`, but also include the fields in the selection
+/// sets of the top-level fields, and in theirs, all the way down
+pub fn all_selections(doc: &q::Document) -> Vec<&q::Field> {
+    selections(doc, true)
+}
+
+fn selections(doc: &q::Document, nested: bool) -> Vec<&q::Field> {
+    let fragments: HashMap<&str, &q::FragmentDefinition> = doc
+        .definitions
+        .iter()
+        .filter_map(|defn| match defn {
+            q::Definition::Fragment(frag) => Some((frag.name.as_str(), frag)),
+            q::Definition::Operation(_) => None,
+        })
+        .collect();
+
+    struct Walker<'a> {
+        fragments: HashMap<&'a str, &'a q::FragmentDefinition>,
+        nested: bool,
+        seen: HashSet<&'a str>,
+        fields: Vec<&'a q::Field>,
+    }
+
+    impl<'a> Walker<'a> {
+        fn collect(&mut self, set: &'a q::SelectionSet) {
+            for item in &set.items {
+                match item {
+                    q::Selection::Field(field) => {
+                        if field.name != "__typename" {
+                            self.fields.push(field);
+                        }
+                        if self.nested {
+                            self.collect(&field.selection_set);
+                        }
+                    }
+                    q::Selection::FragmentSpread(spread) => {
+                        // Guard against fragments that spread themselves
+                        if self.seen.insert(&spread.fragment_name) {
+                            let frag = self.fragments.get(spread.fragment_name.as_str()).copied();
+                            if let Some(frag) = frag {
+                                self.collect(&frag.selection_set);
+                            }
+                        }
+                    }
+                    q::Selection::InlineFragment(frag) => self.collect(&frag.selection_set),
+                }
+            }
+        }
+    }
+
+    let mut walker = Walker {
+        fragments,
+        nested,
+        seen: HashSet::new(),
+        fields: Vec::new(),
+    };
+    for defn in &doc.definitions {
+        use q::OperationDefinition::*;
+        let set = match defn {
+            q::Definition::Operation(SelectionSet(set)) => set,
+            q::Definition::Operation(Query(query)) => &query.selection_set,
+            q::Definition::Operation(Mutation(mutation)) => &mutation.selection_set,
+            q::Definition::Operation(Subscription(subscription)) => &subscription.selection_set,
+            q::Definition::Fragment(_) => continue,
+        };
+        walker.seen.clear();
+        walker.collect(set);
+    }
+    walker.fields
+}
+
+/// The share of `time` that the field at `index` among `count` fields
+/// gets when we split the time of a query evenly between its top-level
+/// fields. The first field gets the remainder so that the shares add up
+/// to `time`
+pub fn share(time: u64, index: usize, count: usize) -> u64 {
+    let count = count as u64;
+    time / count + if index == 0 { time % count } else { 0 }
+}
+
+/// The distinct names of the top-level fields of all operations in `doc`
+/// in the order in which they first appear
+pub fn root_fields(doc: &q::Document) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for field in root_selections(doc) {
+        if !names.contains(&field.name) {
+            names.push(field.name.clone());
+        }
+    }
+    names
+}
+
+/// The statistics for one top-level field, or for one entity type, of a
+/// subgraph
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldInfo {
+    pub subgraph: String,
+    /// The top-level field; the entity type when aggregating by entity
+    /// type
+    pub field: String,
+    /// The entity type the field returns; only known if we have the
+    /// schema of the subgraph
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity: Option<String>,
+    /// The number of queries that contained this field
+    pub calls: u64,
+    /// The time (in ms) attributed to this field
+    pub total_time: u64,
+    /// The longest time attributed to this field in a single query
+    pub max_time: u64,
+    /// The number of slow queries that contained this field
+    pub slow_count: u64,
+}
+
+impl FieldInfo {
+    pub fn avg(&self) -> f64 {
+        self.total_time as f64 / self.calls as f64
+    }
+}
+
+/// Aggregates the time spent on queries by `(subgraph, root field)`, and
+/// by `(subgraph, entity type)` if we have schemas. All queries with the
+/// same shape have the same root fields, so we work off the summaries and
+/// only need to parse one query per shape. The time for a shape is split
+/// between its top-level fields with `share`
+#[derive(Default)]
+pub struct FieldAggregator {
+    schemas: Option<Schemas>,
+    fields: BTreeMap<(String, String), FieldInfo>,
+    entities: BTreeMap<(String, String), FieldInfo>,
+}
+
+/// Add `total_time` and `max_time` of a query from `info` to the
+/// statistics for `(info.subgraph, field)` in `map`
+fn record(
+    map: &mut BTreeMap<(String, String), FieldInfo>,
+    info: &QueryInfo,
+    field: &str,
+    entity: Option<&str>,
+    total_time: u64,
+    max_time: u64,
+) {
+    let stats = map
+        .entry((info.subgraph.clone(), field.to_owned()))
+        .or_insert_with(|| FieldInfo {
+            subgraph: info.subgraph.clone(),
+            field: field.to_owned(),
+            entity: entity.map(str::to_owned),
+            calls: 0,
+            total_time: 0,
+            max_time: 0,
+            slow_count: 0,
+        });
+    stats.calls += info.calls;
+    stats.total_time += total_time;
+    stats.max_time = stats.max_time.max(max_time);
+    stats.slow_count += info.slow_count;
+}
+
+impl FieldAggregator {
+    /// Look up the entity type of each field in `schemas`
+    pub fn with_schemas(schemas: Schemas) -> Self {
+        FieldAggregator {
+            schemas: Some(schemas),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, info: &QueryInfo) {
+        if info.is_truncated() {
+            return;
+        }
+        let names = match shape_hash::parse(&info.query) {
+            Some(doc) => root_fields(&doc),
+            None => return,
+        };
+        if names.is_empty() {
+            return;
+        }
+
+        // The time for each entity type of the query; a query that uses
+        // several fields for the same type only counts once for it
+        let mut entities: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+        let count = names.len();
+        for (i, name) in names.iter().enumerate() {
+            let total_time = share(info.total_time, i, count);
+            let max_time = share(info.max_time, i, count);
+            let entity = self.schemas.as_ref().map(|schemas| {
+                schemas
+                    .root_entity(&info.subgraph, name)
+                    .unwrap_or(UNKNOWN_ENTITY)
+            });
+            record(&mut self.fields, info, name, entity, total_time, max_time);
+            if let Some(entity) = entity {
+                let times = entities.entry(entity).or_default();
+                times.0 += total_time;
+                times.1 += max_time;
+            }
+        }
+        for (entity, (total_time, max_time)) in entities {
+            record(
+                &mut self.entities,
+                info,
+                entity,
+                Some(entity),
+                total_time,
+                max_time,
+            );
+        }
+    }
+
+    pub fn fields(&self) -> Vec<FieldInfo> {
+        self.fields.values().cloned().collect()
+    }
+
+    /// The statistics for each entity type; empty without schemas
+    pub fn entities(&self) -> Vec<FieldInfo> {
+        self.entities.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Schema;
+    use graphql_parser::parse_query;
+
+    #[test]
+    fn find_root_fields() {
+        const QUERY: &str = "query TranscodersQuery($_v0_skip: Int) { \
+            transcoders(skip: $_v0_skip) { id } \
+            ...Rounds \
+            ... on Query { protocol { id } } \
+            __typename \
+        } \
+        fragment Rounds on Query { rounds { id } transcoders { id } }";
+        let doc = parse_query(QUERY).unwrap();
+        assert_eq!(vec!["transcoders", "rounds", "protocol"], root_fields(&doc));
+    }
+
+    #[test]
+    fn split_time() {
+        let mut info = QueryInfo::new(
+            "{ things { id } stuff { id } }".to_owned(),
+            "QmSuBgRaPh".to_owned(),
+            1,
+            0,
+            BTreeMap::new(),
+        );
+        info.calls = 2;
+        info.total_time = 3003;
+        info.max_time = 1501;
+        info.slow_count = 2;
+        let mut other = info.clone();
+        other.query = "{ things(first: 5) { id } }".to_owned();
+
+        let mut fields = FieldAggregator::default();
+        fields.add(&info);
+        fields.add(&other);
+
+        let fields = fields.fields();
+        assert_eq!(2, fields.len());
+        let stuff = fields.iter().find(|info| info.field == "stuff").unwrap();
+        let things = fields.iter().find(|info| info.field == "things").unwrap();
+        assert_eq!(4, things.calls);
+        assert_eq!(1502 + 3003, things.total_time);
+        assert_eq!(1501, things.max_time);
+        assert_eq!(1501, stuff.total_time);
+        assert_eq!(750, stuff.max_time);
+        assert_eq!(2, stuff.slow_count);
+    }
+
+    #[test]
+    fn entity_types() {
+        let mut schemas = Schemas::default();
+        let schema = "type Thing @entity { id: ID! } type Part @entity { id: ID! }";
+        schemas.insert("QmSuBgRaPh".to_owned(), Schema::parse(schema).unwrap());
+        let mut info = QueryInfo::new(
+            "{ thing(id: \"1\") { id } things { id } parts { id } _meta { hash } }".to_owned(),
+            "QmSuBgRaPh".to_owned(),
+            1,
+            0,
+            BTreeMap::new(),
+        );
+        info.calls = 1;
+        info.total_time = 400;
+        info.max_time = 400;
+
+        let mut fields = FieldAggregator::with_schemas(schemas);
+        fields.add(&info);
+
+        let things = fields.fields().into_iter().find(|f| f.field == "things");
+        assert_eq!(Some("Thing"), things.unwrap().entity.as_deref());
+        let entities: Vec<_> = fields
+            .entities()
+            .into_iter()
+            .map(|f| (f.field, f.calls, f.total_time))
+            .collect();
+        assert_eq!(
+            vec![
+                ("(unknown)".to_owned(), 1, 100),
+                ("Part".to_owned(), 1, 100),
+                ("Thing".to_owned(), 1, 200),
+            ],
+            entities
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Report which arguments, and in particular which `where` filters,
+//! `orderBy` columns and `first`/`skip` values, queries use for each
+//! top-level field, and how much time queries with them took. This helps
+//! decide which database indexes a subgraph needs
+use graphql_parser::query as q;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::fields::{root_selections, share};
+use crate::{shape_hash, Entry};
+
+/// The statistics for one argument of a top-level field
+#[derive(Debug, Clone, Serialize)]
+pub struct FilterInfo {
+    pub subgraph: String,
+    pub field: String,
+    /// The argument, e.g. `where.stuff_gt`, `orderBy=timestamp` or
+    /// `skip=1000+`
+    pub attribute: String,
+    /// The number of times the field was queried with this argument
+    pub calls: u64,
+    /// The time (in ms) attributed to the field in those queries
+    pub total_time: u64,
+    /// The longest time attributed to the field in a single query
+    pub max_time: u64,
+}
+
+impl FilterInfo {
+    pub fn avg(&self) -> f64 {
+        self.total_time as f64 / self.calls as f64
+    }
+}
+
+/// Turn a GraphQL value into JSON, replacing variables with their values
+/// from `vars`
+pub(crate) fn resolve(value: &q::Value, vars: &Map<String, Value>) -> Value {
+    match value {
+        q::Value::Variable(name) => vars.get(name).cloned().unwrap_or(Value::Null),
+        q::Value::Int(n) => n.as_i64().map(Value::from).unwrap_or(Value::Null),
+        q::Value::Float(f) => Value::from(*f),
+        q::Value::String(s) | q::Value::Enum(s) => Value::String(s.clone()),
+        q::Value::Boolean(b) => Value::Bool(*b),
+        q::Value::Null => Value::Null,
+        q::Value::List(values) => Value::Array(values.iter().map(|v| resolve(v, vars)).collect()),
+        q::Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, v)| (key.clone(), resolve(v, vars)))
+                .collect(),
+        ),
+    }
+}
+
+/// The largest power of ten that is at most `n`, or 0 if `n` is not
+/// positive
+pub(crate) fn magnitude(n: i64) -> i64 {
+    if n <= 0 {
+        return 0;
+    }
+    let mut power = 1;
+    while power <= n / 10 {
+        power *= 10;
+    }
+    power
+}
+
+/// Put `n` into a bucket `0`, `1+`, `10+`, `100+`, etc. so that we do not
+/// report every distinct value of `first` and `skip` separately
+pub(crate) fn bucket(n: i64) -> String {
+    match magnitude(n) {
+        0 => "0".to_owned(),
+        power => format!("{}+", power),
+    }
+}
+
+/// Add the attributes for the argument `name` with value `value` to
+/// `attrs`. Objects like `where` filters are walked recursively so that
+/// each key becomes its own attribute
+pub(crate) fn attributes(name: &str, value: &Value, top: bool, attrs: &mut BTreeSet<String>) {
+    match (value, name) {
+        (Value::Object(map), _) => {
+            for (key, value) in map {
+                attributes(&format!("{}.{}", name, key), value, false, attrs);
+            }
+        }
+        (Value::Array(values), _) if values.iter().any(Value::is_object) => {
+            for value in values {
+                attributes(name, value, false, attrs);
+            }
+        }
+        (Value::Null, _) => {}
+        (Value::String(s), "orderBy") | (Value::String(s), "orderDirection") if top => {
+            attrs.insert(format!("{}={}", name, s));
+        }
+        (Value::Number(n), "first") | (Value::Number(n), "skip") if top => {
+            attrs.insert(format!("{}={}", name, bucket(n.as_i64().unwrap_or(0))));
+        }
+        _ => {
+            attrs.insert(name.to_owned());
+        }
+    }
+}
+
+/// Aggregates the time spent on queries by `(subgraph, root field,
+/// argument)`. Like for the `fields` report, the time of a query is split
+/// between its distinct top-level fields with `share`
+#[derive(Default)]
+pub struct FilterAggregator {
+    filters: BTreeMap<(String, String, String), FilterInfo>,
+}
+
+impl FilterAggregator {
+    pub fn add(&mut self, entry: &Entry) {
+        if entry.truncated {
+            return;
+        }
+        let doc = match shape_hash::parse(&entry.query) {
+            Some(doc) => doc,
+            None => return,
+        };
+        let vars = match serde_json::from_str(&entry.variables) {
+            Ok(Value::Object(vars)) => vars,
+            _ => Map::new(),
+        };
+
+        // A field that is selected several times, under aliases or through
+        // fragments, counts once with the attributes of all its selections
+        let mut fields: Vec<(&str, BTreeSet<String>)> = Vec::new();
+        for field in root_selections(&doc) {
+            let pos = match fields.iter().position(|(name, _)| *name == field.name) {
+                Some(pos) => pos,
+                None => {
+                    fields.push((&field.name, BTreeSet::new()));
+                    fields.len() - 1
+                }
+            };
+            for (name, value) in &field.arguments {
+                attributes(name, &resolve(value, &vars), true, &mut fields[pos].1);
+            }
+        }
+        let count = fields.len();
+        for (i, (field, attrs)) in fields.into_iter().enumerate() {
+            let time = share(entry.time, i, count);
+            for attr in attrs {
+                let key = (entry.subgraph.to_string(), field.to_owned(), attr);
+                let info = self
+                    .filters
+                    .entry(key)
+                    .or_insert_with_key(|key| FilterInfo {
+                        subgraph: key.0.clone(),
+                        field: key.1.clone(),
+                        attribute: key.2.clone(),
+                        calls: 0,
+                        total_time: 0,
+                        max_time: 0,
+                    });
+                info.calls += 1;
+                info.total_time += time;
+                info.max_time = info.max_time.max(time);
+            }
+        }
+    }
+
+    pub fn filters(&self) -> Vec<FilterInfo> {
+        self.filters.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::fixtures::entry;
+
+    #[test]
+    fn literal_and_variable_arguments() {
+        let entry = Entry {
+            subgraph: "QmSuBgRaPh".into(),
+            query_id: "f2-6b-48-b6-6b".into(),
+            block: 10344025,
+            time: 1001,
+            query: "query things($_v0_where: Thing_filter, $_v1_skip: Int) { \
+                things(where: $_v0_where, skip: $_v1_skip, first: 100, orderBy: timestamp) { id } \
+                stuff(where: { synth: \"x\", or: [{ a_gt: 1 }, { b: 2 }] }) { id } }"
+                .into(),
+            variables:
+                "{\"_v0_where\":{\"stuff_gt\":5,\"owner_\":{\"name\":\"x\"}},\"_v1_skip\":2500}"
+                    .into(),
+            timestamp: None,
+            truncated: false,
+            origin: None,
+        };
+        let mut filters = FilterAggregator::default();
+        filters.add(&entry);
+
+        let attrs: Vec<_> = filters
+            .filters()
+            .into_iter()
+            .map(|info| {
+                // The first field gets the remainder
+                let time = if info.field == "things" { 501 } else { 500 };
+                assert_eq!(time, info.total_time);
+                format!("{}: {}", info.field, info.attribute)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "stuff: where.or.a_gt",
+                "stuff: where.or.b",
+                "stuff: where.synth",
+                "things: first=100+",
+                "things: orderBy=timestamp",
+                "things: skip=1000+",
+                "things: where.owner_.name",
+                "things: where.stuff_gt",
+            ],
+            attrs
+        );
+    }
+
+    #[test]
+    fn repeated_root_fields() {
+        let entry = Entry {
+            query: "{ a: things(first: 1) { id } b: things(first: 2) { id } ...More } \
+                    fragment More on Query { things(skip: 10) { id } }"
+                .into(),
+            ..entry("things", 300)
+        };
+        let mut filters = FilterAggregator::default();
+        filters.add(&entry);
+
+        let attrs: Vec<_> = filters
+            .filters()
+            .into_iter()
+            .map(|info| (info.attribute, info.calls, info.total_time))
+            .collect();
+        assert_eq!(
+            vec![
+                ("first=1+".to_owned(), 1, 300),
+                ("skip=10+".to_owned(), 1, 300)
+            ],
+            attrs
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Read a logfile while it is still being written to, similar to
+//! `tail -F`. We notice when the file is truncated or when logrotate
+//! moves it out of the way and a new file is created in its place
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Identifies the file `meta` belongs to; a file that replaces it under
+/// the same name has a different id
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.ino()
+}
+
+/// Identifies the file `meta` belongs to; a file that replaces it under
+/// the same name has a different id. Without inodes, we go by when the
+/// file was created
+#[cfg(not(unix))]
+fn file_id(meta: &Metadata) -> u64 {
+    meta.created()
+        .ok()
+        .and_then(|created| created.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |created| created.as_nanos() as u64)
+}
+
+pub struct Follower {
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// The `file_id` of the file we are reading; when the file at `path`
+    /// has a different id, it was rotated
+    id: u64,
+    /// How far into the file we have read
+    pos: u64,
+    /// The beginning of a line whose end has not been written yet
+    partial: String,
+}
+
+impl Follower {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let file = File::open(&path)?;
+        let id = file_id(&file.metadata()?);
+        Ok(Follower {
+            path,
+            reader: BufReader::new(file),
+            id,
+            pos: 0,
+            partial: String::new(),
+        })
+    }
+
+    /// Return the next complete line from the file without the trailing
+    /// newline, or `None` if no complete line is available right now
+    pub fn next_line(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let count = self.reader.read_line(&mut self.partial)?;
+            self.pos += count as u64;
+            if self.partial.ends_with('\n') {
+                let mut line = std::mem::take(&mut self.partial);
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                return Ok(Some(line));
+            }
+            if count == 0 && !self.reopen()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Check whether the file was truncated or rotated and start reading
+    /// from the beginning of the new file if it was. Return `true` if we
+    /// did that
+    fn reopen(&mut self) -> io::Result<bool> {
+        let meta = match fs::metadata(&self.path) {
+            Ok(meta) => meta,
+            // The file was moved away and the new one is not there yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if file_id(&meta) != self.id {
+            let file = File::open(&self.path)?;
+            self.id = file_id(&file.metadata()?);
+            self.reader = BufReader::new(file);
+        } else if meta.len() < self.pos {
+            self.reader.seek(SeekFrom::Start(0))?;
+        } else {
+            return Ok(false);
+        }
+        self.pos = 0;
+        // Whatever was left of the old file will never be completed
+        self.partial.clear();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn append(path: &PathBuf, text: &str) {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    fn lines(follower: &mut Follower) -> Vec<String> {
+        let mut lines = vec![];
+        while let Some(line) = follower.next_line().unwrap() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[test]
+    fn truncate_and_rotate() {
+        let dir = std::env::temp_dir().join(format!("qlog-follow-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("queries.log");
+        let _ = fs::remove_file(&path);
+
+        append(&path, "one\ntw");
+        let mut follower = Follower::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(vec!["one"], lines(&mut follower));
+        append(&path, "o\nthree\n");
+        assert_eq!(vec!["two", "three"], lines(&mut follower));
+
+        // Truncation
+        fs::write(&path, "four\n").unwrap();
+        assert_eq!(vec!["four"], lines(&mut follower));
+
+        // Rotation
+        fs::rename(&path, dir.join("queries.log.1")).unwrap();
+        assert!(lines(&mut follower).is_empty());
+        append(&path, "five\n");
+        assert_eq!(vec!["five"], lines(&mut follower));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
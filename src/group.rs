@@ -0,0 +1,176 @@
+//! Additional dimensions by which query statistics can be grouped besides
+//! the shape of the query, like the graph-node instance that served a
+//! query, the database shard that holds the subgraph, or the client that
+//! sent the query
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use crate::Entry;
+
+/// The names of all the dimensions we know about
+pub const DIMENSIONS: &[&str] = &["client", "node", "shard"];
+
+pub enum Dimension {
+    /// The graph-node instance that logged the entry, i.e. its `origin`
+    Node,
+    /// The database shard of the subgraph, taken from a mapping of
+    /// subgraph ids to shard names
+    Shard(HashMap<String, String>),
+    /// The client, e.g. the API key, that sent the query, taken from a
+    /// mapping of query ids to clients; the mapping usually comes from
+    /// the logs of a gateway in front of graph-node
+    Client(HashMap<String, String>),
+}
+
+impl Dimension {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dimension::Node => "node",
+            Dimension::Shard(_) => "shard",
+            Dimension::Client(_) => "client",
+        }
+    }
+
+    fn value<'a>(&'a self, entry: &'a Entry) -> Option<&'a str> {
+        match self {
+            Dimension::Node => entry.origin.as_deref(),
+            Dimension::Shard(shards) => shards.get(entry.subgraph.as_ref()).map(|s| s.as_str()),
+            Dimension::Client(clients) => clients.get(entry.query_id.as_ref()).map(|s| s.as_str()),
+        }
+    }
+}
+
+/// The dimensions by which we group entries
+#[derive(Default)]
+pub struct Grouping {
+    /// Sorted by name so that the groups of an entry are always listed
+    /// in the same order
+    dims: Vec<Dimension>,
+}
+
+impl Grouping {
+    pub fn new(mut dims: Vec<Dimension>) -> Self {
+        dims.sort_by_key(|dim| dim.name());
+        Grouping { dims }
+    }
+
+    /// The `(dimension, value)` pairs for `entry`; dimensions for which
+    /// the entry has no value are left out
+    pub fn groups<'a>(&'a self, entry: &'a Entry) -> Vec<(&'static str, &'a str)> {
+        self.dims
+            .iter()
+            .filter_map(|dim| dim.value(entry).map(|value| (dim.name(), value)))
+            .collect()
+    }
+}
+
+/// Read a mapping from a file with one `key,value` pair per line, or one
+/// JSON object per line whose `key` and `value` fields hold the pair.
+/// Empty lines, lines starting with `#`, and a CSV header line
+/// `key,value` are ignored
+pub fn read_mapping(filename: &str, key: &str, value: &str) -> io::Result<HashMap<String, String>> {
+    read_mapping_from(BufReader::new(File::open(filename)?), key, value)
+}
+
+fn read_mapping_from(
+    reader: impl BufRead,
+    key: &str,
+    value: &str,
+) -> io::Result<HashMap<String, String>> {
+    fn invalid(msg: String) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    }
+
+    let header = format!("{},{}", key, value);
+    let mut mapping = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == header {
+            continue;
+        }
+        if line.starts_with('{') {
+            let obj: Value = serde_json::from_str(line)?;
+            let field = |name: &str| match &obj[name] {
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            };
+            match (field(key), field(value)) {
+                (Some(k), Some(v)) => {
+                    mapping.insert(k, v);
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "expected an object with `{}` and `{}` but got `{}`",
+                        key, value, line
+                    )))
+                }
+            }
+            continue;
+        }
+        match line.split_once(',') {
+            Some((k, v)) => {
+                mapping.insert(k.trim().to_owned(), v.trim().to_owned());
+            }
+            None => return Err(invalid(format!("expected `{}` but got `{}`", header, line))),
+        }
+    }
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::fixtures::entry;
+
+    #[test]
+    fn groups_are_sorted() {
+        let shards = vec![("QmSuBgRaPh".to_owned(), "shard_a".to_owned())]
+            .into_iter()
+            .collect();
+        let grouping = Grouping::new(vec![Dimension::Shard(shards), Dimension::Node]);
+
+        let mut entry = Entry {
+            subgraph: "QmSuBgRaPh".into(),
+            query_id: "f2-6b-48-b6-6b".into(),
+            block: 10344025,
+            time: 125,
+            query: "query { things(id:\"1\") { id }}".into(),
+            variables: "{}".into(),
+            timestamp: None,
+            truncated: false,
+            origin: Some("query-node-1".into()),
+        };
+        assert_eq!(
+            vec![("node", "query-node-1"), ("shard", "shard_a")],
+            grouping.groups(&entry)
+        );
+
+        entry.subgraph = "QmOtHeR".into();
+        entry.origin = None;
+        assert!(grouping.groups(&entry).is_empty());
+    }
+
+    #[test]
+    fn client_mapping() {
+        let text = "query_id,client\n\
+                    # from the gateway\n\
+                    f2-6b-48-b6-6b, key-1\n\
+                    {\"query_id\": \"a1-b2\", \"client\": \"key-2\", \"ip\": \"10.0.0.1\"}\n";
+        let clients = read_mapping_from(text.as_bytes(), "query_id", "client").unwrap();
+        assert_eq!(2, clients.len());
+        assert_eq!(Some("key-2"), clients.get("a1-b2").map(|s| s.as_str()));
+        assert!(read_mapping_from("{\"client\": 1}".as_bytes(), "query_id", "client").is_err());
+
+        let grouping = Grouping::new(vec![Dimension::Node, Dimension::Client(clients)]);
+        let mut entry = entry("things", 125);
+        entry.origin = Some("query-node-1".into());
+        assert_eq!(
+            vec![("client", "key-1"), ("node", "query-node-1")],
+            grouping.groups(&entry)
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! Process and summarize the query logs that
+//! [graph-node](https://github.com/graphprotocol/graph-node) generates.
+//!
+//! Log lines are turned into `Entry` values with an `EntryParser`, and an
+//! `Aggregator` summarizes them into one `QueryInfo` for each distinct
+//! query shape, as determined by `shape_hash`. A `Sampler` takes random
+//! samples of entries.
+pub mod anomaly;
+pub mod cluster;
+pub mod columnar;
+pub mod common;
+pub mod compact;
+pub mod entry;
+pub mod extract;
+pub mod fields;
+pub mod filters;
+pub mod follow;
+pub mod group;
+pub mod lint;
+pub mod metrics;
+pub mod pretty;
+pub mod prometheus;
+pub mod report;
+pub mod sampler;
+pub mod schema;
+pub mod serve;
+pub mod shape_hash;
+pub mod summary;
+pub mod timeseries;
+pub mod tui;
+
+pub use cluster::Cluster;
+pub use entry::{Entry, EntryParser, JsonlEntryParser, TextEntryParser};
+pub use fields::{FieldAggregator, FieldInfo};
+pub use filters::{FilterAggregator, FilterInfo};
+pub use group::{Dimension, Grouping};
+pub use lint::{SkipDetector, SkipInfo};
+pub use metrics::Metrics;
+pub use sampler::Sampler;
+pub use schema::{SchemaInfo, Schemas};
+pub use shape_hash::{shape_hash, ShapeHash};
+pub use summary::{
+    combine, read_summaries, read_summaries_from, regroup, rollup, sort_summaries, write_summaries,
+    Aggregator, QueryInfo, ReadStats, Rollup, Weight,
+};
@@ -0,0 +1,243 @@
+//! Detect queries that paginate with large `skip` values. graph-node has
+//! to read and throw away all the skipped rows, which makes such queries
+//! very slow; paginating with a filter like `id_gt` avoids that. We look
+//! at the `first` and `skip` arguments of all fields in a query, not just
+//! the top-level ones, since nested collections are paginated the same
+//! way
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::fields::all_selections;
+use crate::filters::{magnitude, resolve};
+use crate::{shape_hash, Entry, QueryInfo};
+
+/// The `skip` value above which we consider a query problematic unless
+/// told otherwise
+pub const DEFAULT_SKIP_THRESHOLD: i64 = 5000;
+
+/// How many query ids of offending queries we remember for each shape
+const MAX_OFFENDERS: usize = 5;
+
+/// How one query shape uses `skip`. Queries with `skip` above the
+/// threshold are called 'deep'
+#[derive(Debug, Clone)]
+pub struct SkipInfo {
+    pub subgraph: String,
+    /// The shape hash as computed by `QueryInfo::entry_hash`
+    pub hash: u64,
+    /// The query of the deepest call
+    pub query: String,
+    pub calls: u64,
+    /// The number of calls for each order of magnitude of `skip`, i.e.,
+    /// for `0`, `1+`, `10+`, `100+`, etc.
+    pub histogram: BTreeMap<i64, u64>,
+    /// The same for `first`
+    pub first_histogram: BTreeMap<i64, u64>,
+    pub deep_calls: u64,
+    pub deep_time: u64,
+    pub deep_max_time: u64,
+    /// The total time of calls with `skip` below the threshold
+    pub other_time: u64,
+    pub max_skip: i64,
+    pub max_first: i64,
+    /// `(time, query_id)` of the slowest deep calls, slowest first
+    pub offenders: Vec<(u64, String)>,
+}
+
+impl SkipInfo {
+    fn new(subgraph: String, hash: u64, query: String) -> Self {
+        SkipInfo {
+            subgraph,
+            hash,
+            query,
+            calls: 0,
+            histogram: BTreeMap::new(),
+            first_histogram: BTreeMap::new(),
+            deep_calls: 0,
+            deep_time: 0,
+            deep_max_time: 0,
+            other_time: 0,
+            max_skip: 0,
+            max_first: 0,
+            offenders: Vec::new(),
+        }
+    }
+
+    pub fn deep_percent(&self) -> f64 {
+        self.deep_calls as f64 * 100.0 / self.calls as f64
+    }
+
+    pub fn deep_avg(&self) -> f64 {
+        self.deep_time as f64 / self.deep_calls as f64
+    }
+
+    /// The average time of calls that are not deep, or `None` if all
+    /// calls were deep
+    pub fn other_avg(&self) -> Option<f64> {
+        let calls = self.calls - self.deep_calls;
+        if calls == 0 {
+            None
+        } else {
+            Some(self.other_time as f64 / calls as f64)
+        }
+    }
+}
+
+/// The largest `skip` and the largest `first` that any field in the
+/// query of `entry` uses, or `None` if none of them has a `skip` or a
+/// `first` argument. A missing argument counts as `0`
+fn pagination(entry: &Entry) -> Option<(i64, i64)> {
+    let doc = shape_hash::parse(&entry.query)?;
+    let vars = match serde_json::from_str(&entry.variables) {
+        Ok(Value::Object(vars)) => vars,
+        _ => Map::new(),
+    };
+    let mut found = None;
+    for (name, value) in all_selections(&doc)
+        .into_iter()
+        .flat_map(|field| field.arguments.iter())
+    {
+        if name != "skip" && name != "first" {
+            continue;
+        }
+        let (skip, first) = found.get_or_insert((0, 0));
+        let max = if name == "skip" { skip } else { first };
+        *max = (*max).max(resolve(value, &vars).as_i64().unwrap_or(0));
+    }
+    found
+}
+
+/// Collects the `skip` values that queries use for each shape
+pub struct SkipDetector {
+    threshold: i64,
+    resolve_variables: bool,
+    shapes: BTreeMap<u64, SkipInfo>,
+}
+
+impl SkipDetector {
+    pub fn new(threshold: i64) -> Self {
+        SkipDetector {
+            threshold,
+            resolve_variables: false,
+            shapes: BTreeMap::new(),
+        }
+    }
+
+    /// Group queries into shapes with `QueryInfo::resolved_hash` so that
+    /// the shapes match those of summaries that were produced that way
+    pub fn resolve_variables(&mut self, resolve: bool) {
+        self.resolve_variables = resolve;
+    }
+
+    pub fn add(&mut self, entry: &Entry) {
+        if entry.truncated {
+            return;
+        }
+        let (skip, first) = match pagination(entry) {
+            Some(pagination) => pagination,
+            None => return,
+        };
+        let hash = QueryInfo::entry_hash(entry, self.resolve_variables);
+        let info = self.shapes.entry(hash).or_insert_with(|| {
+            SkipInfo::new(entry.subgraph.to_string(), hash, entry.query.to_string())
+        });
+
+        info.calls += 1;
+        *info.histogram.entry(magnitude(skip)).or_default() += 1;
+        *info.first_histogram.entry(magnitude(first)).or_default() += 1;
+        if skip > info.max_skip {
+            info.max_skip = skip;
+            info.query = entry.query.to_string();
+        }
+        info.max_first = info.max_first.max(first);
+        if skip <= self.threshold {
+            info.other_time += entry.time;
+            return;
+        }
+        info.deep_calls += 1;
+        info.deep_time += entry.time;
+        info.deep_max_time = info.deep_max_time.max(entry.time);
+        let pos = info
+            .offenders
+            .iter()
+            .position(|(time, _)| *time < entry.time)
+            .unwrap_or(info.offenders.len());
+        if pos < MAX_OFFENDERS {
+            info.offenders
+                .insert(pos, (entry.time, entry.query_id.to_string()));
+            info.offenders.truncate(MAX_OFFENDERS);
+        }
+    }
+
+    /// The shapes that had at least one deep call, the ones that spent
+    /// the most time on deep calls first
+    pub fn offenders(&self) -> Vec<SkipInfo> {
+        let mut infos: Vec<_> = self
+            .shapes
+            .values()
+            .filter(|info| info.deep_calls > 0)
+            .cloned()
+            .collect();
+        infos.sort_by_key(|info| std::cmp::Reverse(info.deep_time));
+        infos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(query_id: &str, time: u64, skip: &str) -> Entry<'static> {
+        Entry {
+            subgraph: "QmSuBgRaPh".into(),
+            query_id: query_id.to_owned().into(),
+            block: 10344025,
+            time,
+            query: "query things($_v0_skip: Int) { things(first: 100, skip: $_v0_skip) { id } }"
+                .into(),
+            variables: format!("{{\"_v0_skip\":{}}}", skip).into(),
+            timestamp: None,
+            truncated: false,
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn detect_deep_skips() {
+        let mut detector = SkipDetector::new(1000);
+        detector.add(&entry("q1", 10, "0"));
+        detector.add(&entry("q2", 30, "500"));
+        detector.add(&entry("q3", 2000, "5000"));
+        detector.add(&entry("q4", 4000, "20000"));
+
+        let offenders = detector.offenders();
+        assert_eq!(1, offenders.len());
+        let info = &offenders[0];
+        assert_eq!(4, info.calls);
+        assert_eq!(2, info.deep_calls);
+        assert_eq!(50.0, info.deep_percent());
+        assert_eq!(3000.0, info.deep_avg());
+        assert_eq!(Some(20.0), info.other_avg());
+        assert_eq!(20000, info.max_skip);
+        assert_eq!(100, info.max_first);
+        assert_eq!(
+            vec![(0, 1), (100, 1), (1000, 1), (10000, 1)],
+            info.histogram.clone().into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(4000, "q4".to_owned()), (2000, "q3".to_owned())],
+            info.offenders
+        );
+
+        // Nested fields are paginated, too, and a shape whose calls are
+        // all deep has no average for the other calls
+        let mut detector = SkipDetector::new(1000);
+        detector.add(&Entry {
+            query: "{ things(first: 10) { parts(first: 1000, skip: 9000) { id } } }".into(),
+            ..entry("q5", 500, "0")
+        });
+        let info = &detector.offenders()[0];
+        assert_eq!((9000, 1000), (info.max_skip, info.max_first));
+        assert_eq!(None, info.other_avg());
+    }
+}
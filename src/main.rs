@@ -1,240 +1,293 @@
 use clap::{App, AppSettings, ArgMatches, SubCommand};
-use graphql_parser::parse_query;
-use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
-use std::hash::{Hash, Hasher};
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-mod common;
-mod entry;
-mod extract;
-mod sampler;
-mod shape_hash;
+use signal_hook::consts::{SIGINT, SIGTERM};
 
-use entry::{Entry, EntryParser, JsonlEntryParser, TextEntryParser};
-use sampler::Sampler;
+use qlog::prometheus::{self, Exporter};
+use qlog::{
+    anomaly, cluster, columnar, compact, extract, follow, group, lint, pretty, report, serve,
+    timeseries,
+};
+use qlog::{
+    read_summaries, regroup, sort_summaries, write_summaries, Aggregator, Cluster, Dimension,
+    Entry, EntryParser, FieldAggregator, FieldInfo, FilterAggregator, FilterInfo, Grouping,
+    JsonlEntryParser, QueryInfo, ReadStats, Sampler, Schemas, SkipDetector, SkipInfo,
+    TextEntryParser, Weight,
+};
 
-/// Queries that take longer than this (in ms) are considered slow
-const SLOW_THRESHOLD: u64 = 1000;
+/// Where samples go if the user does not tell us otherwise
+const DEFAULT_SAMPLE_FILE: &str = "/var/tmp/samples.jsonl";
+
+/// Where 'serve' listens if the user does not tell us otherwise
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
 
 pub fn die(msg: &str) -> ! {
     eprintln!("{}", msg);
     std::process::exit(1);
 }
 
-/// The statistics we maintain about each query; we keep queries unique
-/// by `(query, subgraph)`
-///
-/// Changes to this data structure require that the summary files get
-/// regenerated from the processed log files by running `qlog process`
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct QueryInfo {
-    query: String,
-    subgraph: String,
-    /// The total time (in ms) spend on this query
-    total_time: u64,
-    /// The sum of query times squared, for computing standard deviation
-    time_squared: u64,
-    /// The longest a single instance of the query took
-    max_time: u64,
-    /// The UUID of the slowest query; this helps in finding that query
-    /// in the logfile
-    max_uuid: String,
-    /// The variables used in the slowest query
-    max_variables: String,
-    /// The complexity of the slowest query
-    #[serde(default = "zero")]
-    max_complexity: u64,
-    /// The number of times this query took longer than `SLOW_THRESHOLD`
-    slow_count: u64,
-    /// The number of times the query has been run
-    calls: u64,
-    /// An ID to make it easier to refer to the query for the user
-    id: usize,
-    /// The hash value for this query; two `QueryInfo` instances with the
-    /// same `hash` are assumed to refer to the same logical query
-    #[serde(default = "zero")]
-    hash: u64,
-}
-
-fn zero() -> u64 {
-    0
+/// Where `process --output` writes the entries it reads
+enum Output {
+    Jsonl(BufWriter<File>),
+    Parquet(Box<columnar::EntryWriter<BufWriter<File>>>),
 }
 
-impl QueryInfo {
-    fn new(query: String, subgraph: String, id: usize, hash: u64) -> QueryInfo {
-        QueryInfo {
-            query,
-            subgraph,
-            id,
-            total_time: 0,
-            time_squared: 0,
-            max_time: 0,
-            max_uuid: "(none)".to_owned(),
-            max_variables: "null".to_owned(),
-            max_complexity: 0,
-            slow_count: 0,
-            calls: 0,
-            hash,
+impl Output {
+    /// Write Parquet if `filename` ends in `.parquet`, and JSONL otherwise
+    fn create(filename: &str) -> Result<Self, io::Error> {
+        let writer = BufWriter::new(File::create(filename)?);
+        if columnar::is_parquet(filename) {
+            Ok(Output::Parquet(Box::new(columnar::EntryWriter::new(
+                writer,
+            )?)))
+        } else {
+            Ok(Output::Jsonl(writer))
         }
     }
 
-    fn add(&mut self, entry: &Entry) {
-        self.calls += 1;
-        self.total_time += entry.time;
-        self.time_squared += entry.time * entry.time;
-        if entry.time > self.max_time {
-            self.max_time = entry.time;
-            self.max_uuid = entry.query_id.to_string();
-            self.max_variables = entry.variables.to_string();
-            self.query = entry.query.to_string();
-        }
-        if entry.time > SLOW_THRESHOLD {
-            self.slow_count += 1;
+    fn write(&mut self, entry: &Entry) -> Result<(), io::Error> {
+        match self {
+            Output::Jsonl(out) => {
+                let json = serde_json::to_string(entry)?;
+                writeln!(out, "{}", json)
+            }
+            Output::Parquet(out) => out.write(entry),
         }
     }
 
-    fn avg(&self) -> f64 {
-        self.total_time as f64 / self.calls as f64
+    fn flush(&mut self) -> Result<(), io::Error> {
+        match self {
+            Output::Jsonl(out) => out.flush(),
+            Output::Parquet(out) => out.flush(),
+        }
     }
 
-    fn variance(&self) -> f64 {
-        let avg = self.avg();
-        let calls = self.calls as f64;
-        let time_squared = self.time_squared as f64;
-        time_squared / calls - avg * avg
+    /// Finish writing; a Parquet file is not readable until it is closed
+    fn close(self) -> Result<(), io::Error> {
+        match self {
+            Output::Jsonl(mut out) => out.flush(),
+            Output::Parquet(out) => out.close(),
+        }
     }
+}
 
-    fn stddev(&self) -> f64 {
-        self.variance().sqrt()
-    }
+/// Where the `process` subcommand sends every entry besides the summaries
+struct Sinks<'a> {
+    sampler: &'a mut Sampler,
+    /// Prometheus metrics that are kept up to date with every entry
+    metrics: Option<&'a Mutex<Exporter>>,
+    /// Where to write every entry, if anywhere
+    out: &'a mut Option<Output>,
+    /// Print lines that are not queries to stderr
+    print_extra: bool,
+}
 
-    fn combine(&mut self, other: &QueryInfo) {
-        self.calls += other.calls;
-        self.total_time += other.total_time;
-        self.time_squared += other.time_squared;
-        if other.max_time > self.max_time {
-            self.max_time = other.max_time;
-            self.max_uuid = other.max_uuid.clone();
-            self.max_variables = other.max_variables.clone();
-            self.max_complexity = other.max_complexity.clone();
+impl Sinks<'_> {
+    /// Add `entry` to the samples and the metrics, and write it to `out`
+    /// if that is given; `entry` is `None` if `line` is not a query. This
+    /// is everything besides summarizing that `process` does with a line,
+    /// whether it reads logfiles or follows one
+    fn process_entry(&mut self, line: &str, entry: Option<&Entry>) -> Result<(), io::Error> {
+        match entry {
+            Some(entry) => {
+                self.sampler.sample(entry);
+                if let Some(metrics) = self.metrics {
+                    metrics.lock().unwrap().add(entry);
+                }
+                if let Some(out) = self.out.as_mut() {
+                    out.write(entry)?;
+                }
+            }
+            None if self.print_extra => eprintln!("not a query: {}", line),
+            None => {}
         }
-        self.slow_count += other.slow_count;
+        Ok(())
     }
+}
 
-    /// A hash value that can be calculated without constructing
-    /// a `QueryInfo`
-    fn hash(query_id: &str, query: &str, subgraph: &str) -> u64 {
-        let mut hasher = DefaultHasher::new();
-
-        if query_id.matches("-").count() == 1 {
-            // A new style query id in the format {shape_hash}-{hash}
-            let shape_hash = query_id.split("-").next().unwrap();
-            u64::from_str_radix(shape_hash, 16).map_err(|e| {
-                eprintln!(
-                    "query_id looks like it has the shape_hash, but apparently not: {}: {}",
-                    shape_hash,
-                    e.to_string()
-                );
-            })
-        } else {
-            parse_query(query)
-                .map_err(|e| {
-                    eprintln!(
-                        "Failed to parse GraphQL query: {}: {}",
-                        e.to_string(),
-                        query
-                    )
-                })
-                .map(|doc| shape_hash::shape_hash(&doc))
-        }
-        .map(|shape_hash|
-            // We have a shape_hash
-            (shape_hash, subgraph).hash(&mut hasher))
-        .unwrap_or_else(|_|
-            // Fall back to the old way of computing hashes
-            (query, subgraph).hash(&mut hasher));
+/// The heart of the `process` subcommand. Reads query logs from each of
+/// the `inputs`, or from stdin if there are none, and summarizes them
+fn process(
+    inputs: Vec<&str>,
+    parser: &dyn EntryParser,
+    mut gql_queries: Aggregator,
+    sinks: &mut Sinks,
+) -> Result<Vec<QueryInfo>, std::io::Error> {
+    let mut visit = |line: &str, entry: Option<&Entry>| sinks.process_entry(line, entry);
 
-        hasher.finish()
+    let mut total = ReadStats::default();
+    if inputs.is_empty() {
+        total = gql_queries.read(io::stdin().lock(), parser, &mut visit)?;
     }
+    for input in &inputs {
+        let stats = File::open(input)
+            .and_then(|file| gql_queries.read(BufReader::new(file), parser, &mut visit))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", input, e)))?;
+        eprintln!(
+            "{}: {} GraphQL queries in {} lines in {:.3}s (match: {:.3}s)",
+            input,
+            stats.entries,
+            stats.lines,
+            stats.elapsed.as_secs_f64(),
+            stats.parse_time.as_secs_f64(),
+        );
+        total.add(&stats);
+    }
+    eprintln!(
+        "Processed {} GraphQL queries in {:.3}s (match: {:.3}s)",
+        total.entries,
+        total.elapsed.as_secs_f64(),
+        total.parse_time.as_secs_f64(),
+    );
+    if gql_queries.evicted() > 0 {
+        eprintln!(
+            "Kept {} shapes; dropped {} summaries to make room for others",
+            gql_queries.len(),
+            gql_queries.evicted()
+        );
+    }
+    Ok(gql_queries.into_summaries())
+}
 
-    fn read(line: &str) -> Result<QueryInfo, serde_json::Error> {
-        serde_json::from_str(line).map(|mut info: QueryInfo| {
-            if info.hash == 0 {
-                info.hash = QueryInfo::hash("ignore", &info.query, &info.subgraph);
+/// Call `f` for every entry in the `inputs`, or in stdin if there are no
+/// inputs
+fn read_entries(
+    inputs: Vec<&str>,
+    parser: &dyn EntryParser,
+    mut f: impl FnMut(&Entry),
+) -> Result<(), std::io::Error> {
+    fn read(
+        reader: impl BufRead,
+        parser: &dyn EntryParser,
+        f: &mut impl FnMut(&Entry),
+    ) -> Result<(), std::io::Error> {
+        for line in reader.lines() {
+            if let Some(entry) = parser.parse(&line?) {
+                f(&entry);
             }
-            info
-        })
+        }
+        Ok(())
+    }
+
+    if inputs.is_empty() {
+        read(io::stdin().lock(), parser, &mut f)?;
     }
+    for input in inputs {
+        File::open(input)
+            .and_then(|file| read(BufReader::new(file), parser, &mut f))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", input, e)))?;
+    }
+    Ok(())
 }
 
-fn add_entry(queries: &mut BTreeMap<u64, QueryInfo>, entry: &Entry) {
-    let hsh = QueryInfo::hash(&entry.query_id, &entry.query, &entry.subgraph);
-    let count = queries.len();
-    let info = queries.entry(hsh).or_insert_with(|| {
-        QueryInfo::new(
-            entry.query.to_string(),
-            entry.subgraph.to_string(),
-            count + 1,
-            hsh,
-        )
-    });
-    info.add(entry);
+/// Where and how often to write results when following a logfile
+struct Flush {
+    interval: Duration,
+    summaries: Option<String>,
+    samples: Option<String>,
+    schemas: Option<Schemas>,
 }
 
-/// The heart of the `process` subcommand. Expects a logfile containing
-/// query logs on the command line.
-fn process(
-    sampler: &mut Sampler,
-    parser: &dyn EntryParser,
-    print_extra: bool,
-    out: &mut Option<BufWriter<File>>
-) -> Result<Vec<QueryInfo>, std::io::Error> {
-    // Read the file line by line using the lines() iterator from std::io::BufRead.
-    let mut gql_queries: BTreeMap<u64, QueryInfo> = BTreeMap::default();
+/// How long to wait for more data when we reached the end of a logfile
+/// we are following
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
 
-    let start = Instant::now();
+/// The `process` subcommand when it follows a logfile that is still being
+/// written to. Summaries and samples are written according to `flush`
+/// while we go along. This only returns when there is an error, or when
+/// we are told to stop with SIGINT or SIGTERM, in which case everything
+/// is written one last time
+fn follow(
+    filename: &str,
+    flush: &Flush,
+    parser: &dyn EntryParser,
+    mut gql_queries: Aggregator,
+    sinks: &mut Sinks,
+) -> Result<(), std::io::Error> {
+    let mut follower = follow::Follower::open(filename)?;
     let mut gql_lines: usize = 0;
-    let mut mtch = Duration::from_secs(0);
-    for line in io::stdin().lock().lines() {
-        let line = line?;
-
-        let mtch_start = Instant::now();
-        if let Some(entry) = parser.parse(&line) {
-            mtch += mtch_start.elapsed();
-            gql_lines += 1;
-            sampler.sample(&entry);
-            add_entry(&mut gql_queries, &entry);
-            if let Some(ref mut out) = out {
-                let json = serde_json::to_string(&entry)?;
-                writeln!(out, "{}", json)?;
+    let mut last_flush = Instant::now();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in &[SIGINT, SIGTERM] {
+        signal_hook::flag::register(*signal, stop.clone())?;
+    }
+
+    loop {
+        let stopping = stop.load(Ordering::Relaxed);
+        if !stopping {
+            match follower.next_line()? {
+                Some(line) => {
+                    let entry = parser.parse(&line);
+                    if let Some(entry) = &entry {
+                        gql_lines += 1;
+                        gql_queries.add(entry);
+                    }
+                    sinks.process_entry(&line, entry.as_ref())?;
+                }
+                None => std::thread::sleep(POLL_INTERVAL),
+            }
+        }
+
+        if stopping || last_flush.elapsed() >= flush.interval {
+            if let Some(summaries) = &flush.summaries {
+                let infos = annotate(gql_queries.summaries(), &flush.schemas);
+                write_atomically(summaries, |writer| save_summaries(writer, summaries, infos))?;
             }
-        } else if print_extra {
-            eprintln!("not a query: {}", line);
+            if let Some(samples) = &flush.samples {
+                write_atomically(samples, |writer| sinks.sampler.write_to(writer))?;
+            }
+            if let Some(out) = sinks.out.as_mut() {
+                out.flush()?;
+            }
+            eprintln!(
+                "Processed {} GraphQL queries with {} distinct shapes so far",
+                gql_lines,
+                gql_queries.len()
+            );
+            last_flush = Instant::now();
+        }
+        if stopping {
+            return Ok(());
         }
     }
-    eprintln!(
-        "Processed {} GraphQL queries in {:.3}s (match: {:.3}s)",
-        gql_lines,
-        start.elapsed().as_secs_f64(),
-        mtch.as_secs_f64(),
-    );
-    Ok(gql_queries.values().cloned().collect())
 }
 
-/// Read a list of summaries from `filename` The file must be in
-/// 'JSON lines' format, i.e., with one JSON object per line
-fn read_summaries(filename: &str) -> Result<Vec<QueryInfo>, std::io::Error> {
-    let file = std::fs::File::open(filename)?;
-    let reader = BufReader::new(file);
-    let mut infos = vec![];
-    for line in reader.lines() {
-        infos.push(QueryInfo::read(&line?)?);
+/// Write to `filename` by writing to a temporary file first and then
+/// moving that into place so that readers never see a partially written
+/// file
+fn write_atomically(
+    filename: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), std::io::Error>,
+) -> Result<(), std::io::Error> {
+    let tmp = format!("{}.tmp", filename);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(&tmp, filename)
+}
+
+/// Write `infos` to `writer`, which writes to `filename`, in Parquet
+/// format if `filename` ends in `.parquet`, in the compact format if it
+/// ends in `.qlb`, and as JSONL otherwise
+fn save_summaries(
+    writer: &mut BufWriter<File>,
+    filename: &str,
+    infos: Vec<QueryInfo>,
+) -> Result<(), std::io::Error> {
+    if columnar::is_parquet(filename) {
+        columnar::write_summaries(writer, &infos)
+    } else if compact::is_compact(filename) {
+        compact::write_summaries(writer, &infos)
+    } else {
+        write_summaries(writer, infos)
     }
-    Ok(infos)
 }
 
 fn buf_writer(filename: &str) -> BufWriter<File> {
@@ -244,67 +297,349 @@ fn buf_writer(filename: &str) -> BufWriter<File> {
     }
 }
 
-/// Write a list of summaries to stdout; the list will be written in
-/// 'JSON lines' format
-fn write_summaries(writer: &mut dyn Write, infos: Vec<QueryInfo>) -> Result<(), std::io::Error> {
-    for info in infos {
-        let json = serde_json::to_string(&info).unwrap_or_else(|err| {
-            die(&format!(
-                "failed to convert summary to json: {}",
-                err.to_string()
-            ))
-        });
-        write!(writer, "{}\n", json)?;
+/// The 'stats' subcommand
+fn print_stats(queries: Vec<QueryInfo>, dims: &[&str]) {
+    // Use writeln! instead of println! so we do not get a panic on
+    // SIGPIPE if the output is piped into e.g. head -n 1
+    let mut stdout = io::stdout();
+    #[allow(unused_must_use)]
+    {
+        write!(stdout, "| {:^7} |", "QID");
+        for dim in dims {
+            write!(stdout, " {:^20} |", dim);
+        }
+        writeln!(
+            stdout,
+            " {:^8} | {:^10} | {:^5} | {:^6} | {:^11} | {:^12} | {:^6} | {:^6} | {:^6} | {:^6} |",
+            "calls",
+            "complexity",
+            "depth",
+            "fields",
+            "collections",
+            "total",
+            "avg",
+            "stddev",
+            "max",
+            "slow"
+        );
+        write!(stdout, "|---------+");
+        for _ in dims {
+            write!(stdout, "{:-<22}+", "");
+        }
+        writeln!(
+            stdout,
+            "----------+------------+-------+--------+-------------+--------------+--------+--------+--------+--------|"
+        );
+    }
+    for query in &queries {
+        #[allow(unused_must_use)]
+        {
+            write!(stdout, "| Q{:0>6} |", query.id);
+            for dim in dims {
+                let value = query.groups.get(*dim).map(|s| s.as_str()).unwrap_or("-");
+                write!(stdout, " {:<20} |", value);
+            }
+            writeln!(
+                stdout,
+                " {:>8} | {:>10} | {:>5} | {:>6} | {:>11} | {:>12} | {:>6.0} | {:>6.0} | {:>6} | {:>6} |",
+                query.calls,
+                query.max_complexity,
+                query.depth,
+                query.fields,
+                query.collections,
+                query.total_time,
+                query.avg(),
+                query.stddev(),
+                query.max_time,
+                query.slow_count
+            );
+        }
     }
-    Ok(())
 }
 
-fn sort_queries(queries: &mut Vec<QueryInfo>, sort: &str) {
+/// The 'fields' subcommand
+fn print_fields(queries: Vec<QueryInfo>, sort: &str, schemas: Option<Schemas>, by_entity: bool) {
+    let with_entities = schemas.is_some();
+    let mut aggregator = match schemas {
+        Some(schemas) => FieldAggregator::with_schemas(schemas),
+        None => FieldAggregator::default(),
+    };
+    for query in &queries {
+        aggregator.add(query);
+    }
+    let mut fields: Vec<FieldInfo> = if by_entity {
+        aggregator.entities()
+    } else {
+        aggregator.fields()
+    };
     let sort = sort.chars().next().unwrap_or('t');
-    queries.sort_by(|a, b| {
+    fields.sort_by(|a, b| {
         let ord = match sort {
             'c' => a.calls.cmp(&b.calls),
             'a' => a.avg().partial_cmp(&b.avg()).unwrap(),
             'm' => a.max_time.cmp(&b.max_time),
             's' => a.slow_count.cmp(&b.slow_count),
-            'u' => a.max_uuid.cmp(&b.max_uuid),
+            'f' | 'e' => b.field.cmp(&a.field),
             _ => a.total_time.cmp(&b.total_time),
         };
         ord.reverse()
     });
+
+    // Show the entity type of each field if we know it
+    let entity_column = with_entities && !by_entity;
+    let mut stdout = io::stdout();
+    #[allow(unused_must_use)]
+    {
+        let name = if by_entity { "entity" } else { "field" };
+        write!(stdout, "| {:^46} | {:^30} |", "subgraph", name);
+        if entity_column {
+            write!(stdout, " {:^30} |", "entity");
+        }
+        writeln!(
+            stdout,
+            " {:^8} | {:^12} | {:^6} | {:^6} | {:^6} |",
+            "calls", "total", "avg", "max", "slow"
+        );
+        write!(stdout, "|{}+{}+", "-".repeat(48), "-".repeat(32));
+        if entity_column {
+            write!(stdout, "{}+", "-".repeat(32));
+        }
+        writeln!(
+            stdout,
+            "----------+--------------+--------+--------+--------|"
+        );
+        for field in &fields {
+            write!(stdout, "| {:<46} | {:<30} |", field.subgraph, field.field);
+            if entity_column {
+                write!(stdout, " {:<30} |", field.entity.as_deref().unwrap_or("-"));
+            }
+            writeln!(
+                stdout,
+                " {:>8} | {:>12} | {:>6.0} | {:>6} | {:>6} |",
+                field.calls,
+                field.total_time,
+                field.avg(),
+                field.max_time,
+                field.slow_count
+            );
+        }
+    }
 }
 
-/// The 'stats' subcommand
-fn print_stats(queries: Vec<QueryInfo>) {
-    // Use writeln! instead of println! so we do not get a panic on
-    // SIGPIPE if the output is piped into e.g. head -n 1
+/// The 'anomalies' subcommand
+fn print_anomalies(anomalies: Vec<anomaly::Anomaly>) {
+    const QUERY_LEN: usize = 60;
+
+    let mut stdout = io::stdout();
+    #[allow(unused_must_use)]
+    {
+        writeln!(
+            stdout,
+            "{:<10}  {:<9}  {:<46}  {:>20}  {:<12}  {:>10}  {:>10}  {:>7}  query",
+            "day", "kind", "subgraph", "shape", "metric", "value", "baseline", "score"
+        );
+        for anomaly in anomalies {
+            let query: String = anomaly
+                .query
+                .as_deref()
+                .unwrap_or("")
+                .chars()
+                .take(QUERY_LEN)
+                .collect();
+            writeln!(
+                stdout,
+                "{:<10}  {:<9}  {:<46}  {:>20}  {:<12}  {:>10.1}  {:>10.1}  {:>7}  {}",
+                anomaly.day,
+                anomaly.kind.to_string(),
+                anomaly.subgraph,
+                anomaly
+                    .hash
+                    .map(|hash| hash.to_string())
+                    .unwrap_or_default(),
+                anomaly.metric,
+                anomaly.value,
+                anomaly.baseline,
+                anomaly
+                    .score
+                    .map(|score| format!("{:.1}", score))
+                    .unwrap_or_else(|| "new".to_owned()),
+                query
+            );
+        }
+    }
+}
+
+/// The 'timeseries' subcommand
+fn print_timeseries(rows: Vec<timeseries::Row>, format: &str) -> Result<(), io::Error> {
+    let mut stdout = io::stdout();
+    if format == "csv" {
+        writeln!(stdout, "start,count,avg,max,p95")?;
+    }
+    for row in rows {
+        if format == "csv" {
+            writeln!(
+                stdout,
+                "{},{},{:.1},{},{}",
+                row.start, row.count, row.avg, row.max, row.p95
+            )?;
+        } else {
+            writeln!(stdout, "{}", serde_json::to_string(&row)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// The 'filters' subcommand
+fn print_filters(mut filters: Vec<FilterInfo>, sort: &str) {
+    let sort = sort.chars().next().unwrap_or('t');
+    filters.sort_by(|a, b| {
+        let ord = match sort {
+            'c' => a.calls.cmp(&b.calls),
+            'a' => a.avg().partial_cmp(&b.avg()).unwrap(),
+            'm' => a.max_time.cmp(&b.max_time),
+            _ => a.total_time.cmp(&b.total_time),
+        };
+        ord.reverse()
+    });
+
     let mut stdout = io::stdout();
     #[allow(unused_must_use)]
     {
         writeln!(
             stdout,
-            "| {:^7} | {:^8} | {:^8} | {:^12} | {:^6} | {:^6} | {:^6} | {:^6} |",
-            "QID", "calls", "complexity", "total", "avg", "stddev", "max", "slow"
+            "| {:^46} | {:^30} | {:^30} | {:^8} | {:^12} | {:^6} | {:^6} |",
+            "subgraph", "field", "attribute", "calls", "total", "avg", "max"
         );
         writeln!(
             stdout,
-            "|---------+----------+--------------+--------+--------+--------+--------|"
+            "|{}+{}+{}+----------+--------------+--------+--------|",
+            "-".repeat(48),
+            "-".repeat(32),
+            "-".repeat(32)
         );
+        for filter in &filters {
+            writeln!(
+                stdout,
+                "| {:<46} | {:<30} | {:<30} | {:>8} | {:>12} | {:>6.0} | {:>6} |",
+                filter.subgraph,
+                filter.field,
+                filter.attribute,
+                filter.calls,
+                filter.total_time,
+                filter.avg(),
+                filter.max_time
+            );
+        }
     }
-    for query in &queries {
+}
+
+/// The 'lint' subcommand
+fn print_skips(infos: Vec<SkipInfo>, threshold: i64) {
+    fn histogram(histogram: &BTreeMap<i64, u64>) -> String {
+        let buckets: Vec<String> = histogram
+            .iter()
+            .map(|(magnitude, count)| match magnitude {
+                0 => format!("0: {}", count),
+                _ => format!("{}+: {}", magnitude, count),
+            })
+            .collect();
+        buckets.join(", ")
+    }
+
+    let mut stdout = io::stdout();
+    for info in infos {
+        let offenders: Vec<&str> = info.offenders.iter().map(|(_, id)| id.as_str()).collect();
+        let other_avg = info
+            .other_avg()
+            .map(|avg| format!("{:>12.0} ms", avg))
+            .unwrap_or_else(|| format!("{:>12}", "-"));
         #[allow(unused_must_use)]
         {
+            writeln!(stdout, "{:=<32} {} {:=<32}", "", info.hash, "");
+            writeln!(stdout, "# subgraph:        {}", info.subgraph);
+            writeln!(stdout, "# calls:           {:>12}", info.calls);
+            writeln!(stdout, "# skip_histogram:  {}", histogram(&info.histogram));
             writeln!(
                 stdout,
-                "| Q{:0>6} | {:>8} | {:>8} | {:>12} | {:>6.0} | {:>6.0} | {:>6} | {:>6} |",
-                query.id,
-                query.calls,
-                query.max_complexity,
-                query.total_time,
-                query.avg(),
-                query.stddev(),
-                query.max_time,
-                query.slow_count
+                "# first_histogram: {}",
+                histogram(&info.first_histogram)
+            );
+            writeln!(stdout, "# max_skip:        {:>12}", info.max_skip);
+            writeln!(stdout, "# max_first:       {:>12}", info.max_first);
+            writeln!(
+                stdout,
+                "# deep_calls:      {:>12} (skip > {})",
+                info.deep_calls, threshold
+            );
+            writeln!(stdout, "# deep_percent:    {:>12.2} %", info.deep_percent());
+            writeln!(stdout, "# deep_avg_time:   {:>12.0} ms", info.deep_avg());
+            writeln!(stdout, "# deep_max_time:   {:>12} ms", info.deep_max_time);
+            writeln!(stdout, "# other_avg_time:  {}", other_avg);
+            writeln!(stdout, "# query_ids:       {}", offenders.join(" "));
+            writeln!(stdout, "\n{}\n", info.query);
+        }
+    }
+}
+
+/// The 'clusters' subcommand
+fn print_clusters(mut clusters: Vec<Cluster>, sort: &str, full: bool) {
+    let sort = sort.chars().next().unwrap_or('t');
+    clusters.sort_by(|a, b| {
+        let ord = match sort {
+            'c' => a.calls.cmp(&b.calls),
+            'a' => a.avg().partial_cmp(&b.avg()).unwrap(),
+            'm' => a.max_time.cmp(&b.max_time),
+            's' => a.slow_count.cmp(&b.slow_count),
+            _ => a.total_time.cmp(&b.total_time),
+        };
+        ord.reverse()
+    });
+
+    let mut stdout = io::stdout();
+    if !full {
+        #[allow(unused_must_use)]
+        {
+            writeln!(
+                stdout,
+                "| {:^7} | {:^7} | {:^8} | {:^12} | {:^6} | {:^6} | {:^6} | members",
+                "CID", "shapes", "calls", "total", "avg", "max", "slow"
+            );
+            writeln!(
+                stdout,
+                "|---------+---------+----------+--------------+--------+--------+--------+--------"
+            );
+        }
+    }
+    for cluster in &clusters {
+        let members: Vec<String> = cluster
+            .members
+            .iter()
+            .map(|id| format!("Q{}", id))
+            .collect();
+        #[allow(unused_must_use)]
+        if full {
+            writeln!(stdout, "{:=<32} C{} {:=<32}", "", cluster.id, "");
+            writeln!(stdout, "# subgraph:      {}", cluster.subgraph);
+            writeln!(stdout, "# shapes:          {:>12}", cluster.members.len());
+            writeln!(stdout, "# calls:           {:>12}", cluster.calls);
+            writeln!(stdout, "# slow_count:      {:>12}", cluster.slow_count);
+            writeln!(stdout, "# total_time:      {:>12} ms", cluster.total_time);
+            writeln!(stdout, "# avg_time:        {:>12.0} ms", cluster.avg());
+            writeln!(stdout, "# max_time:        {:>12} ms", cluster.max_time);
+            writeln!(stdout, "# members:       {}", members.join(" "));
+            writeln!(stdout, "\n{}\n", cluster.query);
+        } else {
+            writeln!(
+                stdout,
+                "| C{:0>6} | {:>7} | {:>8} | {:>12} | {:>6.0} | {:>6} | {:>6} | {}",
+                cluster.id,
+                cluster.members.len(),
+                cluster.calls,
+                cluster.total_time,
+                cluster.avg(),
+                cluster.max_time,
+                cluster.slow_count,
+                members.join(" ")
             );
         }
     }
@@ -313,28 +648,19 @@ fn print_stats(queries: Vec<QueryInfo>) {
 /// The 'combine' subcommand. Reads summaries from 'filenames' and prints
 /// the summary resulting from combining all those summaries
 fn combine(filenames: Vec<&str>) -> Vec<QueryInfo> {
-    let mut infos: BTreeMap<u64, QueryInfo> = BTreeMap::default();
-    for filename in filenames {
-        for info in read_summaries(filename).unwrap_or_else(|err| {
+    let infos = filenames.into_iter().flat_map(|filename| {
+        read_summaries(filename).unwrap_or_else(|err| {
             die(&format!(
                 "combine: could not read summaries from {}: {}",
-                filename,
-                err.to_string()
+                filename, err
             ))
-        }) {
-            infos
-                .entry(info.hash)
-                .and_modify(|existing| existing.combine(&info))
-                .or_insert(info);
-        }
-    }
-    for (indx, value) in infos.values_mut().enumerate() {
-        value.id = indx;
-    }
-    infos.values().cloned().collect()
+        })
+    });
+    qlog::combine(infos)
 }
 
-fn print_full_query(info: &QueryInfo) {
+/// Write everything we know about `info` except for the query itself
+fn write_details(out: &mut dyn Write, info: &QueryInfo) -> Result<(), io::Error> {
     fn human_readable_time(time: u64) -> (f64, &'static str) {
         const SECS_PER_MINUTE: u64 = 60;
         const SECS_PER_HOUR: u64 = 60 * SECS_PER_MINUTE;
@@ -354,36 +680,83 @@ fn print_full_query(info: &QueryInfo) {
         }
     }
 
+    writeln!(out, "{:=<32} Q{} {:=<32}", "", info.id, "")?;
+    writeln!(out, "# subgraph:      {}", info.subgraph)?;
+    for (dim, value) in &info.groups {
+        writeln!(out, "# {:<14} {}", format!("{}:", dim), value)?;
+    }
+    writeln!(out, "# calls:           {:>12}", info.calls)?;
+    writeln!(out, "# complexity:      {:>12}", info.max_complexity)?;
+    writeln!(out, "# depth:           {:>12}", info.depth)?;
+    writeln!(out, "# fields:          {:>12}", info.fields)?;
+    writeln!(out, "# collections:     {:>12}", info.collections)?;
+    writeln!(out, "# slow_count:      {:>12}", info.slow_count)?;
+    writeln!(
+        out,
+        "# slow_percent:    {:>12.2} %",
+        info.slow_count as f64 * 100.0 / info.calls as f64
+    )?;
+    let (amount, unit) = human_readable_time(info.total_time);
+    writeln!(out, "# total_time:      {:>12.1} {}", amount, unit)?;
+    writeln!(out, "# avg_time:        {:>12.0} ms", info.avg())?;
+    writeln!(out, "# stddev_time:     {:>12.0} ms", info.stddev())?;
+    writeln!(out, "# max_time:        {:>12} ms", info.max_time)?;
+    if info.error > 0 {
+        writeln!(out, "# error:           {:>12}", info.error)?;
+    }
+    writeln!(out, "# max_uuid:      {}", info.max_uuid)?;
+    let variables = pretty::variables(&info.max_variables).replace('\n', "\n# ");
+    writeln!(out, "# max_variables: {}", variables)?;
+    if let Some(schema) = &info.schema {
+        let entities: Vec<&str> = schema.entities.iter().map(|e| e.as_str()).collect();
+        writeln!(out, "# entities:      {}", entities.join(", "))?;
+        writeln!(out, "# lists:           {:>12}", schema.lists)?;
+        writeln!(out, "# singles:         {:>12}", schema.singles)?;
+        writeln!(out, "# derived:         {:>12}", schema.derived)?;
+        for error in &schema.errors {
+            writeln!(out, "# invalid:       {}", error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print all details of `info` and its query formatted over several
+/// lines, with syntax highlighting if `color` is set
+fn print_full_query(info: &QueryInfo, color: bool) {
+    let query = pretty::query(&info.query);
+    let query = if color {
+        pretty::highlight(&query)
+    } else {
+        query
+    };
+    // Ignore errors so we do not get a panic on SIGPIPE if the output is
+    // piped into e.g. head -n 1
     let mut stdout = io::stdout();
-    #[allow(unused_must_use)]
-    {
-        writeln!(stdout, "{:=<32} Q{} {:=<32}", "", info.id, "");
-        writeln!(stdout, "# subgraph:      {}", info.subgraph);
-        writeln!(stdout, "# calls:           {:>12}", info.calls);
-        writeln!(stdout, "# complexity:      {:>12}", info.max_time);
-        writeln!(stdout, "# slow_count:      {:>12}", info.slow_count);
-        writeln!(
-            stdout,
-            "# slow_percent:    {:>12.2} %",
-            info.slow_count as f64 * 100.0 / info.calls as f64
-        );
-        let (amount, unit) = human_readable_time(info.total_time);
-        writeln!(stdout, "# total_time:      {:>12.1} {}", amount, unit);
-        writeln!(stdout, "# avg_time:        {:>12.0} ms", info.avg());
-        writeln!(stdout, "# stddev_time:     {:>12.0} ms", info.stddev());
-        writeln!(stdout, "# max_time:        {:>12} ms", info.max_time);
-        writeln!(stdout, "# max_uuid:      {}", info.max_uuid);
-        writeln!(stdout, "# max_variables: {}", info.max_variables);
-        writeln!(stdout, "\n{}", info.query);
+    write_details(&mut stdout, info)
+        .and_then(|_| write!(stdout, "\n{}", query))
+        .ok();
+}
+
+/// Whether to highlight output according to the `--color` option: by
+/// default only when stdout is a terminal and `NO_COLOR` is not set
+fn use_color(args: &ArgMatches) -> bool {
+    match args.value_of("color").unwrap_or("auto") {
+        "always" => true,
+        "never" => false,
+        "auto" => io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        other => die(&format!(
+            "unknown value for --color: {}; use one of auto, always or never",
+            other
+        )),
     }
 }
 
 /// The 'queries' subcommand
-fn print_queries(filename: &str, queries: Vec<&str>) -> Result<(), std::io::Error> {
+fn print_queries(filename: &str, queries: Vec<&str>, color: bool) -> Result<(), std::io::Error> {
     let infos = read_summaries(filename)?;
     for (count, query) in queries.iter().enumerate() {
-        if query.starts_with("Q") {
-            let qid: usize = match query[1..].parse() {
+        if let Some(qid) = query.strip_prefix('Q') {
+            let qid: usize = match qid.parse() {
                 Err(_) => {
                     eprintln!("skipping invalid query identifier {}", query);
                     continue;
@@ -392,9 +765,9 @@ fn print_queries(filename: &str, queries: Vec<&str>) -> Result<(), std::io::Erro
             };
             if let Some(info) = infos.iter().find(|info| info.id == qid) {
                 if count > 0 {
-                    println!("");
+                    println!();
                 }
-                print_full_query(info);
+                print_full_query(info, color);
             }
         }
     }
@@ -406,9 +779,7 @@ fn make_sampler(args: &ArgMatches) -> Sampler {
         .value_of("samples")
         .map(|s| s.parse::<usize>().expect("'samples' is a number"))
         .unwrap_or(0);
-    let samples_file = args
-        .value_of("sample-file")
-        .unwrap_or("/var/tmp/samples.jsonl");
+    let samples_file = args.value_of("sample-file").unwrap_or(DEFAULT_SAMPLE_FILE);
     let samples_subgraphs = args
         .value_of("sample-subgraphs")
         .map(|s| {
@@ -416,7 +787,7 @@ fn make_sampler(args: &ArgMatches) -> Sampler {
                 .map(|t| t.to_owned())
                 .collect::<HashSet<String>>()
         })
-        .unwrap_or(HashSet::new());
+        .unwrap_or_default();
     if samples > 0 {
         println!(
             "Taking {} samples and writing them to {}",
@@ -434,6 +805,89 @@ fn make_sampler(args: &ArgMatches) -> Sampler {
     Sampler::new(samples, samples_subgraphs, buf_writer(samples_file))
 }
 
+/// The dimensions listed in the `group-by` argument
+fn group_by<'a>(args: &'a ArgMatches) -> Vec<&'a str> {
+    let dims: Vec<&str> = args
+        .value_of("group-by")
+        .map(|s| s.split(',').filter(|dim| !dim.is_empty()).collect())
+        .unwrap_or_default();
+    for dim in &dims {
+        if !group::DIMENSIONS.contains(dim) {
+            die(&format!(
+                "unknown dimension `{}`; must be one of {}",
+                dim,
+                group::DIMENSIONS.join(", ")
+            ));
+        }
+    }
+    dims
+}
+
+/// Load the schemas from the directory given with `--schemas`, if any
+fn load_schemas(args: &ArgMatches) -> Option<Schemas> {
+    args.value_of("schemas").map(|dir| {
+        let schemas = Schemas::load(dir)
+            .unwrap_or_else(|err| die(&format!("could not read schemas from {}: {}", dir, err)));
+        eprintln!("Loaded {} schemas from {}", schemas.len(), dir);
+        schemas
+    })
+}
+
+/// Annotate `infos` with what their schemas tell us if we have schemas
+fn annotate(mut infos: Vec<QueryInfo>, schemas: &Option<Schemas>) -> Vec<QueryInfo> {
+    if let Some(schemas) = schemas {
+        for info in &mut infos {
+            schemas.annotate(info);
+        }
+    }
+    infos
+}
+
+fn make_grouping(args: &ArgMatches) -> Grouping {
+    let dims = group_by(args);
+    for (arg, dim) in &[("shards", "shard"), ("clients", "client")] {
+        if args.is_present(arg) && !dims.contains(dim) {
+            die(&format!("--{} requires --group-by {}", arg, dim));
+        }
+    }
+    let dims = dims
+        .into_iter()
+        .map(|dim| match dim {
+            "node" => Dimension::Node,
+            "shard" => {
+                let filename = args
+                    .value_of("shards")
+                    .unwrap_or_else(|| die("grouping by shard requires a --shards file"));
+                let shards =
+                    group::read_mapping(filename, "subgraph", "shard").unwrap_or_else(|err| {
+                        die(&format!("could not read shards from {}: {}", filename, err))
+                    });
+                Dimension::Shard(shards)
+            }
+            "client" => {
+                let filename = args
+                    .value_of("clients")
+                    .unwrap_or_else(|| die("grouping by client requires a --clients file"));
+                let clients =
+                    group::read_mapping(filename, "query_id", "client").unwrap_or_else(|err| {
+                        die(&format!(
+                            "could not read clients from {}: {}",
+                            filename, err
+                        ))
+                    });
+                eprintln!(
+                    "Loaded clients for {} query ids from {}",
+                    clients.len(),
+                    filename
+                );
+                Dimension::Client(clients)
+            }
+            _ => unreachable!("group_by only returns known dimensions"),
+        })
+        .collect();
+    Grouping::new(dims)
+}
+
 fn main() {
     let args = App::new("qlog")
         .version("1.0")
@@ -442,12 +896,14 @@ fn main() {
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .subcommand(
             SubCommand::with_name("extract")
-                .about("Read StackDriver log files and print the textPayLoad to the SQL or GraphQL output file")
+                .about("Read StackDriver, CloudWatch, or Loki log exports and print the log lines to the SQL or GraphQL output file")
                 .args_from_usage(
                     "-v, --verbose  'Print which files are being read on stderr'
                     graphql -g, --graphql=<FILE> 'Write GraphQL summary to this file'
                     -t, --text 'Write plain text instead of JSONL'
-                    <dir> 'The directory containing StackDriver files'",
+                    [format] -f, --format=<FORMAT> 'The format of the log export: stackdriver (default), cloudwatch, or loki'
+                    [origin] --origin=<LABELS> 'Comma-separated list of labels that identify the graph-node instance (default depends on format)'
+                    <dir> 'The directory containing log export files'",
                 ),
         )
         .subcommand(
@@ -456,11 +912,24 @@ fn main() {
                 .args_from_usage(
                     "-e, --extra 'Print lines that are not recognized as queries on stderr'
                      -t, --text 'Input is in plain text format, not jsonl'
-                     -o, --output=<FILE> 'Save input formatted as jsonl in this file'
-                     [graphql] -g, --graphql=<FILE> Write GraphQL summary to this file
+                     [output] -o, --output=<FILE> 'Save input formatted as jsonl in this file, or as Parquet if it ends in .parquet'
+                     [graphql] -g, --graphql=<FILE> 'Write GraphQL summary to this file, as Parquet if it ends in .parquet'
                      [samples] --samples=<NUMBER> 'Number of samples to take'
                      [sample-file] --sample-file=<FILE> 'Where to write samples'
-                     [sample-subgraphs] --sample-subgraphs=<LIST> 'Which subgraphs to sample'",
+                     [sample-subgraphs] --sample-subgraphs=<LIST> 'Which subgraphs to sample'
+                     [group-by] --group-by=<DIMS> 'Comma-separated list of dimensions (client, node, shard) to break summaries down by'
+                     [shards] --shards=<FILE> 'File with lines of the form `subgraph,shard`'
+                     [clients] --clients=<FILE> 'File with lines of the form `query_id,client`, or JSON objects with query_id and client'
+                     [schemas] --schemas=<DIR> 'Directory with files <subgraph id>.graphql used to annotate summaries'
+                     --resolve-variables 'Substitute variables into queries before computing their shape; this only changes how queries are grouped into shapes'
+                     [top] --top=<K> 'Only keep summaries for the K heaviest shapes to bound memory use'
+                     [top-by] --top-by=<STAT> 'How to rank shapes for --top: total_time or calls (default: total_time)'
+                     [metrics] --metrics=<ADDR> 'Serve Prometheus metrics about the queries on this address at /metrics'
+                     [metrics-max-shapes] --metrics-max-shapes=<N> 'Maximum number of shapes to export metrics for (default: 500)'
+                     --keep-serving 'Keep serving metrics after all logfiles have been processed until interrupted'
+                     [follow] --follow=<FILE> 'Keep reading this logfile as it grows instead of reading stdin'
+                     [flush-interval] --flush-interval=<SECS> 'How often to write summaries and samples when following a logfile (default: 60)'
+                     [input]... 'Logfiles to process; stdin is read if none are given'",
                 ),
         )
         .subcommand(
@@ -470,22 +939,135 @@ fn main() {
                 .args_from_usage(
                     "-s, --sort=[SORT]  'Sort by this column (default: total_time)'
                      -f, --full         'Print full query details'
+                     [color] --color=<WHEN> 'Highlight queries with --full: auto, always or never (default: auto)'
+                     [group-by] --group-by=<DIMS> 'Comma-separated list of dimensions to break statistics down by'
+                     [min-complexity] --min-complexity=<NUMBER> 'Only show queries with at least this complexity'
+                     [min-depth] --min-depth=<NUMBER> 'Only show queries nested at least this deeply'
+                     [min-fields] --min-fields=<NUMBER> 'Only show queries that select at least this many fields'
+                     [min-collections] --min-collections=<NUMBER> 'Only show queries that select at least this many collections'
+                     [schemas] --schemas=<DIR> 'Directory with files <subgraph id>.graphql used to annotate summaries'
+                     --invalid 'Only show queries that fail validation against their schema'
+                     <summary>",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fields")
+                .about("Show how much time was spent on each top-level field of a subgraph")
+                .after_help(FIELDS_HELP_TEXT)
+                .args_from_usage(
+                    "-s, --sort=[SORT]  'Sort by this column (default: total_time)'
+                     [schemas] --schemas=<DIR> 'Directory with files <subgraph id>.graphql used to find the entity type of each field'
+                     --by-entity 'Show totals for each entity type instead of each field; requires --schemas'
+                     <summary>",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("anomalies")
+                .about("Find days on which query shapes or subgraphs behaved unusually")
+                .after_help(ANOMALIES_HELP_TEXT)
+                .args_from_usage(
+                    "[threshold] --threshold=<SCORE> 'Report values with a robust z-score above this (default: 3.5)'
+                     [window] --window=<DAYS> 'Number of preceding days to use as the baseline (default: 14)'
+                     [min-calls] --min-calls=<N> 'Ignore shapes and subgraphs with fewer calls on a day (default: 100)'
+                     <dir> 'Directory with summary files named YYYY-MM-DD.jsonl'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("timeseries")
+                .about("Show how the latency of one query shape changes over time")
+                .after_help(TIMESERIES_HELP_TEXT)
+                .args_from_usage(
+                    "-s, --shape=<SHAPE> 'The shape to follow: Q<id> from the summary given with --summary, or a shape hash'
+                     [summary] --summary=<FILE> 'Summary file in which to look up Q<id>'
+                     [interval] -i, --interval=<INTERVAL> 'Length of each time bucket, like 30s, 5m, 1h or 1d (default: 5m)'
+                     [format] -f, --format=<FORMAT> 'Output format: csv (default) or json'
+                     --resolve-variables 'Substitute variables into queries before computing their shape, as for process'
+                     [input]... 'Logfiles as written by process --output; stdin is read if none are given'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("filters")
+                .about("Show which filters and arguments queries use for each top-level field")
+                .after_help(FILTERS_HELP_TEXT)
+                .args_from_usage(
+                    "-t, --text 'Input is in plain text format, not jsonl'
+                     -s, --sort=[SORT]  'Sort by this column (default: total_time)'
+                     [input]... 'Logfiles to read; stdin is read if none are given'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("Find queries that paginate with large skip values")
+                .after_help(LINT_HELP_TEXT)
+                .args_from_usage(
+                    "-t, --text 'Input is in plain text format, not jsonl'
+                     [skip-threshold] --skip-threshold=<NUMBER> 'Report queries with a skip larger than this (default: 5000)'
+                     --resolve-variables 'Substitute variables into queries before computing their shape, as for process'
+                     [input]... 'Logfiles to read; stdin is read if none are given'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("clusters")
+                .about("Group similar query shapes into clusters")
+                .after_help(CLUSTERS_HELP_TEXT)
+                .args_from_usage(
+                    "-s, --sort=[SORT]  'Sort by this column (default: total_time)'
+                     -f, --full         'Print full cluster details'
+                     [threshold] --threshold=<SIMILARITY> 'How similar shapes must be to be clustered, between 0 and 1 (default: 0.8)'
+                     <summary>",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Render summaries as a self-contained HTML page")
+                .args_from_usage(
+                    "[html] --html=<FILE> 'Write the report to this file instead of stdout'
+                     [title] --title=<TITLE> 'Title of the report (default: Query performance report)'
+                     [top] --top=<N> 'Number of shapes in each of the top lists (default: 20)'
                      <summary>",
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("tui")
+                .about("Browse summaries interactively")
+                .after_help(TUI_HELP_TEXT)
+                .args_from_usage("<summary>"),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serve summaries as a JSON API over HTTP")
+                .after_help(SERVE_HELP_TEXT)
+                .args_from_usage(
+                    "[listen] -l, --listen=<ADDR> 'Address to listen on (default: 127.0.0.1:8080)'
+                     <summary>... 'Summary files; several are combined as with 'combine''",
+                ),
+        )
         .subcommand(
             SubCommand::with_name("query")
                 .about("Show details about a specific query")
                 .after_help(QUERY_HELP_TEXT)
                 .args_from_usage(
-                    "<summary>
+                    "[color] --color=<WHEN> 'Highlight queries: auto, always or never (default: auto)'
+                     <summary>
                      <query>...",
                 ),
         )
         .subcommand(
             SubCommand::with_name("combine")
                 .about("Combine multiple summary files into one")
-                .args_from_usage("<file>..."),
+                .args_from_usage(
+                    "[output] -o, --output=<FILE> 'Write the combined summary to this file instead of stdout; the format depends on the extension'
+                     <file>...",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("Convert a summary file between JSONL, the compact binary format, and Parquet")
+                .after_help(CONVERT_HELP_TEXT)
+                .args_from_usage(
+                    "<input> 'The summary file to convert; its format is detected automatically'
+                     <output> 'Where to write the summaries; the format depends on the extension'",
+                ),
         )
         .get_matches();
 
@@ -498,48 +1080,150 @@ fn main() {
             let dir = args.value_of("dir").expect("'dir' is mandatory");
             let verbose = args.is_present("verbose");
             let text = args.is_present("text");
+            let format: extract::Format = args
+                .value_of("format")
+                .unwrap_or("stackdriver")
+                .parse()
+                .unwrap_or_else(|err: String| die(&format!("extract: {}", err)));
+            let labels: Vec<String> = args
+                .value_of("origin")
+                .unwrap_or_else(|| format.default_labels())
+                .split(',')
+                .filter(|label| !label.is_empty())
+                .map(|label| label.to_owned())
+                .collect();
             let out = writer_for(args, "graphql");
             let result = if text {
                 let mut out = extract::TextWriter::new(out);
-                extract::run(dir, &mut out, verbose)
+                extract::run(dir, &mut out, format, &labels, verbose)
             } else {
                 let mut out = extract::JsonlWriter::new(out, verbose);
-                extract::run(dir, &mut out, verbose)
+                extract::run(dir, &mut out, format, &labels, verbose)
             };
-            result.unwrap_or_else(|err| die(&format!("extract: {}", err.to_string())));
+            result.unwrap_or_else(|err| die(&format!("extract: {}", err)));
         }
         ("process", Some(args)) => {
             let extra = args.is_present("extra");
             let text = args.is_present("text");
-            let mut gql = writer_for(args, "graphql");
             let mut sampler = make_sampler(args);
-            let mut out = args.value_of("output").map(|_| writer_for(args, "output"));
+            let mut queries = Aggregator::new(make_grouping(args));
+            queries.resolve_variables(args.is_present("resolve-variables"));
+            if let Some(top) = args.value_of("top") {
+                let top = top
+                    .parse::<usize>()
+                    .unwrap_or_else(|_| die(&format!("invalid number for --top: {}", top)));
+                let weight = args
+                    .value_of("top-by")
+                    .unwrap_or("total_time")
+                    .parse::<Weight>()
+                    .unwrap_or_else(|err| die(&err));
+                queries.limit(top, weight);
+            }
+            let mut out = args.value_of("output").map(|filename| {
+                if columnar::is_parquet(filename) && args.is_present("follow") {
+                    // Following never finishes, and a Parquet file is only
+                    // readable once it has been closed
+                    die("process: --output can not be a Parquet file with --follow");
+                }
+                Output::create(filename).unwrap_or_else(|err| {
+                    die(&format!("process: failed to open `{}`: {}", filename, err))
+                })
+            });
+            if args.is_present("keep-serving") && !args.is_present("metrics") {
+                die("process: --keep-serving requires --metrics");
+            }
+            if args.is_present("follow") && args.is_present("input") {
+                die("process: --follow can not be combined with input files");
+            }
+            let metrics = args.value_of("metrics").map(|addr| {
+                let max_shapes = args
+                    .value_of("metrics-max-shapes")
+                    .map(|s| {
+                        s.parse::<usize>()
+                            .expect("'metrics-max-shapes' is a number")
+                    })
+                    .unwrap_or(prometheus::DEFAULT_MAX_SHAPES);
+                let mut exporter = Exporter::new(max_shapes);
+                exporter.resolve_variables(args.is_present("resolve-variables"));
+                let exporter = Arc::new(Mutex::new(exporter));
+                let addr = addr.to_owned();
+                let server = {
+                    let exporter = exporter.clone();
+                    std::thread::spawn(move || {
+                        prometheus::serve(&addr, exporter)
+                            .unwrap_or_else(|err| die(&format!("process: metrics: {}", err)))
+                    })
+                };
+                (exporter, server)
+            });
+
+            let mut sinks = Sinks {
+                sampler: &mut sampler,
+                metrics: metrics.as_ref().map(|(exporter, _)| exporter.as_ref()),
+                out: &mut out,
+                print_extra: extra,
+            };
+
+            if let Some(filename) = args.value_of("follow") {
+                let interval = args
+                    .value_of("flush-interval")
+                    .map(|s| s.parse::<u64>().expect("'flush-interval' is a number"))
+                    .unwrap_or(60);
+                let flush = Flush {
+                    interval: Duration::from_secs(interval),
+                    summaries: args.value_of("graphql").map(|s| s.to_owned()),
+                    samples: args.value_of("samples").map(|_| {
+                        args.value_of("sample-file")
+                            .unwrap_or(DEFAULT_SAMPLE_FILE)
+                            .to_owned()
+                    }),
+                    schemas: load_schemas(args),
+                };
+                let result = if text {
+                    follow(filename, &flush, &TextEntryParser {}, queries, &mut sinks)
+                } else {
+                    follow(filename, &flush, &JsonlEntryParser {}, queries, &mut sinks)
+                };
+                result.unwrap_or_else(|err| {
+                    die(&format!("process: failed to follow {}: {}", filename, err))
+                });
+                return;
+            }
 
+            let inputs = args
+                .values_of("input")
+                .map(|v| v.collect())
+                .unwrap_or_default();
+            let mut gql = writer_for(args, "graphql");
             let result = if text {
-                let parser = TextEntryParser {};
-                process(&mut sampler, &parser, extra, &mut out)
+                process(inputs, &TextEntryParser {}, queries, &mut sinks)
             } else {
-                let parser = JsonlEntryParser {};
-                process(&mut sampler, &parser, extra, &mut out)
+                process(inputs, &JsonlEntryParser {}, queries, &mut sinks)
             };
-            let gql_infos = result.unwrap_or_else(|err| {
-                die(&format!(
-                    "process: failed to parse logfile: {}",
-                    err.to_string()
-                ))
-            });
-            write_summaries(&mut gql, gql_infos).unwrap_or_else(|err| {
+            let gql_infos = result
+                .unwrap_or_else(|err| die(&format!("process: failed to parse logfile: {}", err)));
+            let gql_infos = annotate(gql_infos, &load_schemas(args));
+            let gql_file = args.value_of("graphql").unwrap_or("/dev/null");
+            save_summaries(&mut gql, gql_file, gql_infos).unwrap_or_else(|err| {
                 die(&format!(
                     "process: failed to write GraphQL logfile: {}",
-                    err.to_string()
-                ))
-            });
-            sampler.write().unwrap_or_else(|err| {
-                die(&format!(
-                    "process: failed to write samples: {}",
-                    err.to_string()
+                    err
                 ))
             });
+            if let Some(out) = out {
+                out.close().unwrap_or_else(|err| {
+                    die(&format!("process: failed to write output: {}", err))
+                });
+            }
+            sampler
+                .write()
+                .unwrap_or_else(|err| die(&format!("process: failed to write samples: {}", err)));
+            if let Some((_, server)) = metrics.filter(|_| args.is_present("keep-serving")) {
+                // Keep serving so that the final values can be scraped
+                drop(gql);
+                eprintln!("Done processing; serving metrics until interrupted");
+                server.join().ok();
+            }
         }
         ("stats", args) => {
             let args = args.expect("arguments are mandatory for this command");
@@ -549,20 +1233,237 @@ fn main() {
                 .unwrap_or_else(|| die("stats: missing summary file"));
             let sort = args.value_of("sort").unwrap_or("total_time");
             let full = args.is_present("full");
-            let mut queries = read_summaries(summary).unwrap_or_else(|err| {
-                die(&format!(
-                    "stats: could not read summaries: {}",
-                    err.to_string()
-                ))
+            let color = use_color(args);
+            let dims = group_by(args);
+            let queries = read_summaries(summary)
+                .unwrap_or_else(|err| die(&format!("stats: could not read summaries: {}", err)));
+            let min = |name| {
+                args.value_of(name)
+                    .map(|s| s.parse::<u64>().expect("the minimum is a number"))
+                    .unwrap_or(0)
+            };
+            let (min_complexity, min_depth) = (min("min-complexity"), min("min-depth"));
+            let (min_fields, min_collections) = (min("min-fields"), min("min-collections"));
+            let invalid = args.is_present("invalid");
+            let mut queries = annotate(regroup(queries, &dims), &load_schemas(args));
+            queries.retain(|query| {
+                query.max_complexity >= min_complexity
+                    && query.depth >= min_depth
+                    && query.fields >= min_fields
+                    && query.collections >= min_collections
+                    && (!invalid || query.schema.iter().any(|schema| !schema.errors.is_empty()))
             });
-            sort_queries(&mut queries, sort);
+            sort_summaries(&mut queries, sort);
             if full {
                 for query in queries {
-                    print_full_query(&query);
+                    print_full_query(&query, color);
                 }
             } else {
-                print_stats(queries);
+                print_stats(queries, &dims);
+            }
+        }
+        ("fields", args) => {
+            let args = args.expect("arguments are mandatory for this command");
+            let summary = args
+                .value_of("summary")
+                .unwrap_or_else(|| die("fields: missing summary file"));
+            let sort = args.value_of("sort").unwrap_or("total_time");
+            let queries = read_summaries(summary)
+                .unwrap_or_else(|err| die(&format!("fields: could not read summaries: {}", err)));
+            let by_entity = args.is_present("by-entity");
+            if by_entity && !args.is_present("schemas") {
+                die("fields: --by-entity requires --schemas");
+            }
+            print_fields(queries, sort, load_schemas(args), by_entity);
+        }
+        ("anomalies", Some(args)) => {
+            let dir = args.value_of("dir").expect("'dir' is mandatory");
+            let threshold = args
+                .value_of("threshold")
+                .map(|s| s.parse::<f64>().expect("'threshold' is a number"))
+                .unwrap_or(anomaly::DEFAULT_THRESHOLD);
+            let window = args
+                .value_of("window")
+                .map(|s| s.parse::<usize>().expect("'window' is a number"))
+                .unwrap_or(anomaly::DEFAULT_WINDOW);
+            let min_calls = args
+                .value_of("min-calls")
+                .map(|s| s.parse::<u64>().expect("'min-calls' is a number"))
+                .unwrap_or(anomaly::DEFAULT_MIN_CALLS);
+
+            let mut files: Vec<_> = std::fs::read_dir(dir)
+                .unwrap_or_else(|err| die(&format!("anomalies: could not read {}: {}", dir, err)))
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter_map(|name| anomaly::day_of(&name).map(|day| (day.to_owned(), name.clone())))
+                .collect();
+            files.sort();
+            if files.is_empty() {
+                die(&format!(
+                    "anomalies: no summary files named YYYY-MM-DD.jsonl in {}",
+                    dir
+                ));
+            }
+            let days: Vec<_> = files
+                .into_iter()
+                .map(|(day, name)| {
+                    let path = std::path::Path::new(dir).join(&name);
+                    let path = path.to_string_lossy();
+                    let infos = read_summaries(&path).unwrap_or_else(|err| {
+                        die(&format!(
+                            "anomalies: could not read summaries from {}: {}",
+                            path, err
+                        ))
+                    });
+                    (day, infos)
+                })
+                .collect();
+            print_anomalies(anomaly::detect(&days, threshold, window, min_calls));
+        }
+        ("timeseries", Some(args)) => {
+            let shape = args.value_of("shape").expect("'shape' is mandatory");
+            let interval = timeseries::parse_interval(args.value_of("interval").unwrap_or("5m"))
+                .unwrap_or_else(|err| die(&format!("timeseries: {}", err)));
+            let format = args.value_of("format").unwrap_or("csv");
+            if format != "csv" && format != "json" {
+                die(&format!(
+                    "timeseries: unknown format {}; use csv or json",
+                    format
+                ));
+            }
+            let hash = match shape.strip_prefix('Q') {
+                Some(qid) => {
+                    let qid: usize = qid.parse().unwrap_or_else(|_| {
+                        die(&format!("timeseries: invalid query id {}", shape))
+                    });
+                    let summary = args.value_of("summary").unwrap_or_else(|| {
+                        die("timeseries: --summary is needed to look up a query id")
+                    });
+                    let infos = read_summaries(summary).unwrap_or_else(|err| {
+                        die(&format!("timeseries: could not read summaries: {}", err))
+                    });
+                    infos
+                        .iter()
+                        .find(|info| info.id == qid)
+                        .map(|info| info.hash)
+                        .unwrap_or_else(|| {
+                            die(&format!("timeseries: no query {} in {}", shape, summary))
+                        })
+                }
+                None => shape
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| die(&format!("timeseries: invalid shape hash {}", shape))),
+            };
+            let inputs = args
+                .values_of("input")
+                .map(|v| v.collect())
+                .unwrap_or_default();
+
+            let mut series = timeseries::TimeSeries::new(hash, interval);
+            series.resolve_variables(args.is_present("resolve-variables"));
+            read_entries(inputs, &JsonlEntryParser {}, |entry| series.add(entry))
+                .unwrap_or_else(|err| die(&format!("timeseries: failed to read logfile: {}", err)));
+            if series.skipped > 0 {
+                eprintln!(
+                    "Ignored {} queries for the shape without a timestamp",
+                    series.skipped
+                );
+            }
+            print_timeseries(series.rows(), format)
+                .unwrap_or_else(|err| die(&format!("timeseries: failed to write: {}", err)));
+        }
+        ("filters", Some(args)) => {
+            let text = args.is_present("text");
+            let sort = args.value_of("sort").unwrap_or("total_time");
+            let inputs = args
+                .values_of("input")
+                .map(|v| v.collect())
+                .unwrap_or_default();
+            let mut filters = FilterAggregator::default();
+            let result = if text {
+                read_entries(inputs, &TextEntryParser {}, |entry| filters.add(entry))
+            } else {
+                read_entries(inputs, &JsonlEntryParser {}, |entry| filters.add(entry))
+            };
+            result.unwrap_or_else(|err| die(&format!("filters: failed to read logfile: {}", err)));
+            print_filters(filters.filters(), sort);
+        }
+        ("lint", Some(args)) => {
+            let text = args.is_present("text");
+            let threshold = args
+                .value_of("skip-threshold")
+                .map(|s| s.parse::<i64>().expect("'skip-threshold' is a number"))
+                .unwrap_or(lint::DEFAULT_SKIP_THRESHOLD);
+            let inputs = args
+                .values_of("input")
+                .map(|v| v.collect())
+                .unwrap_or_default();
+            let mut detector = SkipDetector::new(threshold);
+            detector.resolve_variables(args.is_present("resolve-variables"));
+            let result = if text {
+                read_entries(inputs, &TextEntryParser {}, |entry| detector.add(entry))
+            } else {
+                read_entries(inputs, &JsonlEntryParser {}, |entry| detector.add(entry))
+            };
+            result.unwrap_or_else(|err| die(&format!("lint: failed to read logfile: {}", err)));
+            print_skips(detector.offenders(), threshold);
+        }
+        ("clusters", Some(args)) => {
+            let summary = args
+                .value_of("summary")
+                .unwrap_or_else(|| die("clusters: missing summary file"));
+            let sort = args.value_of("sort").unwrap_or("total_time");
+            let full = args.is_present("full");
+            let threshold = args
+                .value_of("threshold")
+                .map(|s| s.parse::<f64>().expect("'threshold' is a number"))
+                .unwrap_or(cluster::DEFAULT_THRESHOLD);
+            let queries = read_summaries(summary)
+                .unwrap_or_else(|err| die(&format!("clusters: could not read summaries: {}", err)));
+            print_clusters(cluster::cluster(&queries, threshold), sort, full);
+        }
+        ("report", Some(args)) => {
+            let summary = args
+                .value_of("summary")
+                .unwrap_or_else(|| die("report: missing summary file"));
+            let title = args.value_of("title").unwrap_or("Query performance report");
+            let top = args
+                .value_of("top")
+                .map(|s| s.parse::<usize>().expect("'top' is a number"))
+                .unwrap_or(report::DEFAULT_TOP);
+            let queries = read_summaries(summary)
+                .unwrap_or_else(|err| die(&format!("report: could not read summaries: {}", err)));
+            let page = report::html(&queries, title, top);
+            match args.value_of("html") {
+                Some(file) => std::fs::write(file, page),
+                None => io::stdout().write_all(page.as_bytes()),
             }
+            .unwrap_or_else(|err| die(&format!("report: could not write report: {}", err)));
+        }
+        ("tui", Some(args)) => {
+            let summary = args
+                .value_of("summary")
+                .unwrap_or_else(|| die("tui: missing summary file"));
+            let queries = read_summaries(summary)
+                .unwrap_or_else(|err| die(&format!("tui: could not read summaries: {}", err)));
+            let details = |info: &QueryInfo| {
+                let mut buf = Vec::new();
+                write_details(&mut buf, info).ok();
+                String::from_utf8_lossy(&buf).into_owned()
+            };
+            qlog::tui::run(queries, &details).unwrap_or_else(|err| die(&format!("tui: {}", err)));
+        }
+        ("serve", Some(args)) => {
+            let listen = args.value_of("listen").unwrap_or(DEFAULT_LISTEN);
+            let files = args
+                .values_of("summary")
+                .expect("'summary' is a mandatory argument")
+                .map(|s| s.to_owned())
+                .collect();
+            let summaries = serve::Summaries::load(files)
+                .unwrap_or_else(|err| die(&format!("serve: could not read summaries: {}", err)));
+            eprintln!("Serving {} summaries on http://{}", summaries.len(), listen);
+            serve::serve(listen, summaries).unwrap_or_else(|err| die(&format!("serve: {}", err)));
         }
         ("query", args) => {
             let args = args.expect("arguments are mandatory for this command");
@@ -573,27 +1474,42 @@ fn main() {
                 .values_of("query")
                 .expect("'query' is a mandatory argument")
                 .collect();
-            print_queries(summary, queries).unwrap_or_else(|err| {
-                die(&format!(
-                    "query: could not print queries: {}",
-                    err.to_string()
-                ))
-            });
+            print_queries(summary, queries, use_color(args))
+                .unwrap_or_else(|err| die(&format!("query: could not print queries: {}", err)));
         }
         ("combine", args) => {
+            let args = args.expect("arguments are mandatory for this command");
             let files = args
-                .expect("arguments are mandatory for this command")
                 .values_of("file")
                 .expect("'file' is a mandatory argument")
                 .collect();
 
             let infos = combine(files);
-            write_summaries(&mut io::stdout(), infos).unwrap_or_else(|err| {
+            let result = match args.value_of("output") {
+                Some(output) => {
+                    write_atomically(output, |writer| save_summaries(writer, output, infos))
+                }
+                None => write_summaries(&mut io::stdout(), infos),
+            };
+            result.unwrap_or_else(|err| {
+                die(&format!("combine: failed to write summary file: {}", err))
+            });
+        }
+        ("convert", args) => {
+            let args = args.expect("arguments are mandatory for this command");
+            let input = args.value_of("input").expect("'input' is mandatory");
+            let output = args.value_of("output").expect("'output' is mandatory");
+
+            let infos = read_summaries(input).unwrap_or_else(|err| {
                 die(&format!(
-                    "combine: failed to write summary file: {}",
-                    err.to_string()
+                    "convert: could not read summaries from {}: {}",
+                    input, err
                 ))
             });
+            write_atomically(output, |writer| save_summaries(writer, output, infos))
+                .unwrap_or_else(|err| {
+                    die(&format!("convert: failed to write {}: {}", output, err))
+                });
         }
         _ => die("internal error: no other subcommands exist"),
     }
@@ -608,6 +1524,12 @@ const QUERY_HELP_TEXT: &str =
 # subgraph:        subgraph id
 # calls:           number of times the query was run against
 #                  the database
+# complexity:      estimated number of entities the slowest query
+#                  can return, based on the `first` of its collections
+# depth:           how deeply the fields of the query are nested
+# fields:          number of fields the query selects
+# collections:     number of fields that are collections, i.e., that
+#                  have arguments like `first` or `where`
 # slow_count:      number of times a query took longer than 1s
 # slow_percent:    slow_count / calls * 100
 # total_time:      total time the queries took
@@ -615,10 +1537,160 @@ const QUERY_HELP_TEXT: &str =
 # stddev_time:     standard deviation of the time queries took
 # max_time:        maximum time it took to serve a query from
 #                  the database
+# error:           only when summaries were produced with 'process
+#                  --top': how much the ranking statistic might be
+#                  undercounted because earlier entries were dropped
 # max_uuid:        query_id of a query that took max_time
 # max_variables:   variables that were passed to the invocation
 #                  that took max_time
 
+When summaries were annotated with schemas, these lines follow:
+
+# entities:        the entity types the query touches
+# lists:           number of fields that return a list of entities
+# singles:         number of fields that return a single entity
+# derived:         number of @derivedFrom fields the query traverses
+# invalid:         why the query would fail validation, one line
+#                  per problem
+
 graphql query processed so that most values in filters etc. are
-extracted into variables
+extracted into variables, formatted over several lines and highlighted
+when stdout is a terminal
+";
+
+// Help text for the 'fields' subcommand
+const FIELDS_HELP_TEXT: &str = "Attribute the time spent on queries to the top-level fields of the\
+\nqueries, e.g., `things` in `{ things(where: { ... }) { id } }`, and show\
+\ntotals for each subgraph and field across all query shapes. The time of a\
+\nquery is split evenly between its top-level fields; the first field gets\
+\nwhat is left over when the time can not be divided evenly.
+
+The 'calls' and 'slow' columns count the queries that contained the field,\
+\nand 'max' is the largest time attributed to the field in a single query.
+
+With --schemas, the entity type each field returns is shown, too; fields\
+\nthat are not in the schema have the entity type '(unknown)'. With\
+\n--by-entity, the times of all fields that return the same entity type,\
+\nlike `thing` and `things`, are added up, and queries that contain several\
+\nof them count only once for it.
+";
+
+// Help text for the 'anomalies' subcommand
+const ANOMALIES_HELP_TEXT: &str =
+    "Compare each day with the --window days before it and list what looks\
+\nunusual: shapes whose number of calls, average time or percentage of slow\
+\nqueries is much higher than usual, shapes that were never seen before and\
+\ntake at least 1% of the day's time, and subgraphs that get many more calls\
+\nthan usual. How unusual a value is is measured with the robust z-score,\
+\nwhich uses the median and the median absolute deviation of the preceding\
+\ndays. Shapes and subgraphs need at least 3 days of history before they\
+\nare compared. The shape column is the shape hash, which can be passed to\
+\n'timeseries --shape'.
+";
+
+// Help text for the 'timeseries' subcommand
+const TIMESERIES_HELP_TEXT: &str =
+    "Read query logs as produced by 'process --output', pick the queries with\
+\nthe given shape and put them into buckets of --interval length by their\
+\ntimestamp. For each bucket that has queries, print its start in UTC, the\
+\nnumber of queries, and their average, maximum and 95th percentile time in\
+\nms. Queries without a timestamp are ignored.
+";
+
+// Help text for the 'filters' subcommand
+const FILTERS_HELP_TEXT: &str =
+    "Read query logs as produced by 'process --output' (or plain text logs with\
+\n--text) and list the arguments that queries pass to each top-level field\
+\ntogether with how much time those queries took. Arguments are taken from\
+\nthe query itself or from its variables.
+
+Each key of an object argument like `where` is listed separately, e.g.\
+\n`where.stuff_gt` or `where.owner_.name`. For `orderBy` and\
+\n`orderDirection` the value is shown, e.g. `orderBy=timestamp`, and values\
+\nof `first` and `skip` are shown as powers of ten, e.g. `skip=1000+` for\
+\nvalues between 1000 and 9999. The time of a query is split between its\
+\ntop-level fields as for 'qlog fields'; a field that is selected several\
+\ntimes, e.g. under different aliases, counts once with the arguments of\
+\nall its selections.
+";
+
+// Help text for the 'lint' subcommand
+const LINT_HELP_TEXT: &str =
+    "Read query logs as produced by 'process --output' (or plain text logs with\
+\n--text) and report query shapes that use large `skip` values, which force\
+\ngraph-node to read and throw away all the skipped rows. The `first` and\
+\n`skip` arguments of all fields, including nested ones, are considered.\
+\nQueries with a `skip` above the threshold are called 'deep'. Shapes are\
+\nlisted with the ones that spent the most time on deep queries first, in\
+\nthis format:
+
+================================ shape hash ================================
+# subgraph:        subgraph id
+# calls:           number of queries with this shape that use first or skip
+# skip_histogram:  number of queries for each order of magnitude of skip
+# first_histogram: number of queries for each order of magnitude of first
+# max_skip:        largest skip used
+# max_first:       largest first used
+# deep_calls:      number of queries with skip above the threshold
+# deep_percent:    deep_calls / calls * 100
+# deep_avg_time:   average time of deep queries
+# deep_max_time:   maximum time of a deep query
+# other_avg_time:  average time of the remaining queries, '-' if there are none
+# query_ids:       query_id of the slowest deep queries
+
+the query with the largest skip
+
+The query_ids are taken from the log entries of the deep queries themselves,\
+\nnot from the max_uuid of a summary or from samples, so that each of them\
+\nidentifies a query that used a large skip.
+";
+
+// Help text for the 'tui' subcommand
+const TUI_HELP_TEXT: &str = "Show summaries in a table together with the details of the selected\
+\nsummary, as 'qlog query' would print them. These keys are available:
+
+q, Esc              quit
+Up/Down, k/j        select the previous/next summary
+Home/End            select the first/last summary
+s, S                sort by the next/previous column
+/                   filter summaries by a substring of their query or\
+\n                    subgraph, or by their id like Q12; Enter finishes
+PgUp/PgDn           scroll the details
+y                   copy the max_uuid of the selected summary to the\
+\n                    clipboard; this needs a terminal that supports OSC 52
+";
+
+// Help text for the 'serve' subcommand
+const SERVE_HELP_TEXT: &str =
+    "Serve summaries as a JSON API. The summary files are reread whenever one\
+\nof them changes. The API has these endpoints:
+
+GET /shapes         list summaries; accepts the parameters 'sort' (any\
+\n                    column of 'stats', default total_time), 'subgraph',\
+\n                    'min_calls', 'search' (substring of the query),\
+\n                    'offset' and 'limit' (default 50, at most 1000)
+GET /shapes/Q<id>   the summary with that query id
+GET /shapes/<hash>  all summaries with that shape hash
+GET /subgraphs      totals for each subgraph
+";
+
+// Help text for the 'clusters' subcommand
+const CLUSTERS_HELP_TEXT: &str =
+    "Group query shapes that are similar, for example because they only differ\
+\nin one selected field, into clusters and show totals for each cluster. The\
+\nsimilarity of two shapes is the fraction of features they share, where the\
+\nfeatures of a shape are its root fields, the arguments it passes, and the\
+\npaths of all the fields it selects.
+
+The members of a cluster are listed with their query ids, and 'qlog query'\
+\nshows the details of each of them.
+";
+
+// Help text for the 'convert' subcommand
+const CONVERT_HELP_TEXT: &str =
+    "The format of the output is determined by its file name: files ending in\
+\n'.qlb' are written in a compact binary format that is much faster to read\
+\nthan JSONL, files ending in '.parquet' in the Parquet format, and all other\
+\nfiles as JSONL. Every command that reads summaries accepts all of these\
+\nformats.
 ";
@@ -0,0 +1,160 @@
+//! Static metrics for a query that can be computed from the query text
+//! alone: how deeply it nests, how many fields it selects, and a rough
+//! estimate of how many entities it can return.
+//!
+//! Without the subgraph's schema we can not tell which fields are lists.
+//! We treat a field as a collection if it has one of the arguments that
+//! graph-node only accepts for collections, like `first` or `where`
+use graphql_parser::query as q;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::filters::resolve;
+
+/// The number of entities graph-node returns for a collection if the
+/// query does not specify `first`
+pub const DEFAULT_FIRST: u64 = 100;
+
+/// Arguments that mark a field as a collection
+const COLLECTION_ARGS: &[&str] = &["first", "skip", "where", "orderBy", "orderDirection"];
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metrics {
+    /// The deepest level at which a field is selected; `{ things { id } }`
+    /// has depth 2
+    pub depth: u64,
+    /// The number of fields selected anywhere in the query, not counting
+    /// `__typename`
+    pub fields: u64,
+    /// The number of fields that are collections
+    pub collections: u64,
+    /// The maximum number of entities the query can return. For each
+    /// collection, this is the product of the `first` values of it and
+    /// all the collections it is nested in, and the cost is the sum of
+    /// that over all collections
+    pub cost: u64,
+}
+
+struct Walker<'a> {
+    fragments: HashMap<&'a str, &'a q::FragmentDefinition>,
+    vars: Map<String, Value>,
+    /// The fragments we are currently expanding, to guard against
+    /// fragments that spread themselves
+    stack: Vec<&'a str>,
+    metrics: Metrics,
+}
+
+impl<'a> Walker<'a> {
+    fn first(&self, field: &q::Field) -> u64 {
+        field
+            .arguments
+            .iter()
+            .find(|(name, _)| name == "first")
+            .and_then(|(_, value)| resolve(value, &self.vars).as_u64())
+            .unwrap_or(DEFAULT_FIRST)
+    }
+
+    fn walk(&mut self, set: &'a q::SelectionSet, depth: u64, rows: u64) {
+        for item in &set.items {
+            match item {
+                q::Selection::Field(field) => {
+                    if field.name == "__typename" {
+                        continue;
+                    }
+                    self.metrics.fields += 1;
+                    self.metrics.depth = self.metrics.depth.max(depth);
+                    let is_collection = field
+                        .arguments
+                        .iter()
+                        .any(|(name, _)| COLLECTION_ARGS.contains(&name.as_str()));
+                    let rows = if is_collection {
+                        let rows = rows.saturating_mul(self.first(field));
+                        self.metrics.collections += 1;
+                        self.metrics.cost = self.metrics.cost.saturating_add(rows);
+                        rows
+                    } else {
+                        rows
+                    };
+                    self.walk(&field.selection_set, depth + 1, rows);
+                }
+                q::Selection::FragmentSpread(spread) => {
+                    let name = spread.fragment_name.as_str();
+                    if self.stack.contains(&name) {
+                        continue;
+                    }
+                    if let Some(frag) = self.fragments.get(name).copied() {
+                        self.stack.push(name);
+                        self.walk(&frag.selection_set, depth, rows);
+                        self.stack.pop();
+                    }
+                }
+                q::Selection::InlineFragment(frag) => self.walk(&frag.selection_set, depth, rows),
+            }
+        }
+    }
+}
+
+/// Compute the metrics for `doc`, taking the values of `first` from
+/// `variables` if they are passed that way
+pub fn metrics(doc: &q::Document, variables: &str) -> Metrics {
+    let fragments = doc
+        .definitions
+        .iter()
+        .filter_map(|defn| match defn {
+            q::Definition::Fragment(frag) => Some((frag.name.as_str(), frag)),
+            q::Definition::Operation(_) => None,
+        })
+        .collect();
+    let vars = match serde_json::from_str(variables) {
+        Ok(Value::Object(vars)) => vars,
+        _ => Map::new(),
+    };
+    let mut walker = Walker {
+        fragments,
+        vars,
+        stack: Vec::new(),
+        metrics: Metrics::default(),
+    };
+    for defn in &doc.definitions {
+        use q::OperationDefinition::*;
+        let set = match defn {
+            q::Definition::Operation(SelectionSet(set)) => set,
+            q::Definition::Operation(Query(query)) => &query.selection_set,
+            q::Definition::Operation(Mutation(mutation)) => &mutation.selection_set,
+            q::Definition::Operation(Subscription(subscription)) => &subscription.selection_set,
+            q::Definition::Fragment(_) => continue,
+        };
+        walker.walk(set, 1, 1);
+    }
+    walker.metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graphql_parser::parse_query;
+
+    #[test]
+    fn nested_collections() {
+        const QUERY: &str = "query things($_v0_first: Int) { \
+            things(first: $_v0_first) { id owner { id } ...Stuff __typename } \
+            protocol(id: \"1\") { id } \
+        } \
+        fragment Stuff on Thing { stuff(where: { a: 1 }) { id } }";
+        let doc = parse_query(QUERY).unwrap();
+
+        let expected = Metrics {
+            depth: 3,
+            fields: 8,
+            collections: 2,
+            cost: 10 + 10 * DEFAULT_FIRST,
+        };
+        assert_eq!(expected, metrics(&doc, "{\"_v0_first\":10}"));
+
+        let expected = Metrics {
+            cost: DEFAULT_FIRST + DEFAULT_FIRST * DEFAULT_FIRST,
+            ..expected
+        };
+        assert_eq!(expected, metrics(&doc, "null"));
+    }
+}
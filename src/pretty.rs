@@ -0,0 +1,124 @@
+//! Format queries and variables for people to read. graph-node logs
+//! queries on one long line; we reformat them from the parsed document
+//! with one field per line and indentation, and can highlight the result
+//! with ANSI colors for display in a terminal
+use serde_json::Value;
+
+const RESET: &str = "\x1b[0m";
+const KEYWORD: &str = "\x1b[1;34m";
+const VARIABLE: &str = "\x1b[35m";
+const NAME: &str = "\x1b[33m";
+const STRING: &str = "\x1b[32m";
+const CONSTANT: &str = "\x1b[36m";
+const TYPENAME: &str = "\x1b[2m";
+
+const KEYWORDS: &[&str] = &["query", "mutation", "subscription", "fragment", "on"];
+const CONSTANTS: &[&str] = &["true", "false", "null"];
+
+/// The query formatted over several lines with indentation, or the query
+/// itself if it can not be parsed
+pub fn query(query: &str) -> String {
+    graphql_parser::parse_query(query)
+        .map(|doc| doc.to_string())
+        .unwrap_or_else(|_| query.to_owned())
+}
+
+/// The JSON text `variables` formatted over several lines with
+/// indentation, or `variables` itself if it is not valid JSON
+pub fn variables(variables: &str) -> String {
+    serde_json::from_str::<Value>(variables)
+        .and_then(|vars| serde_json::to_string_pretty(&vars))
+        .unwrap_or_else(|_| variables.to_owned())
+}
+
+/// Add ANSI colors to the GraphQL text `query`: keywords, variables,
+/// strings and constants, names that are followed by a colon, like
+/// arguments and aliases, and `__typename` each get their own color
+pub fn highlight(query: &str) -> String {
+    fn paint(out: &mut String, color: &str, text: &[char]) {
+        out.push_str(color);
+        out.extend(text);
+        out.push_str(RESET);
+    }
+
+    let chars: Vec<char> = query.chars().collect();
+    let mut out = String::with_capacity(query.len() * 2);
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        i += 1;
+        if c == '"' {
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(chars.len());
+            paint(&mut out, STRING, &chars[start..i]);
+        } else if c == '-' || c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || ".+-".contains(chars[i]))
+            {
+                i += 1;
+            }
+            paint(&mut out, CONSTANT, &chars[start..i]);
+        } else if c == '$' || c == '@' || c == '_' || c.is_alphabetic() {
+            while i < chars.len() && (chars[i] == '_' || chars[i].is_alphanumeric()) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let colon = chars[i..]
+                .iter()
+                .find(|c| !c.is_whitespace())
+                .is_some_and(|c| *c == ':');
+            let color = if c == '$' || c == '@' {
+                VARIABLE
+            } else if word == "__typename" {
+                TYPENAME
+            } else if colon {
+                NAME
+            } else if KEYWORDS.contains(&word.as_str()) {
+                KEYWORD
+            } else if CONSTANTS.contains(&word.as_str()) {
+                CONSTANT
+            } else {
+                out.push_str(&word);
+                continue;
+            };
+            paint(&mut out, color, &chars[start..i]);
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_and_highlight() {
+        let text =
+            query("query q($w: Thing_filter) { things(where: $w, first: 10) { id __typename } }");
+        assert_eq!(
+            "query q($w: Thing_filter) {\n  things(where: $w, first: 10) {\n    id\n    __typename\n  }\n}\n",
+            text
+        );
+        // Queries we can not parse are left alone
+        assert_eq!("{ things", query("{ things"));
+
+        assert_eq!(
+            "\x1b[1;34mquery\x1b[0m q(\x1b[35m$w\x1b[0m: Int) { \
+             \x1b[33mthings\x1b[0m: stuff(\x1b[33mid\x1b[0m: \x1b[32m\"a\\\"b\"\x1b[0m, \
+             \x1b[33mfirst\x1b[0m: \x1b[36m-10\x1b[0m) { id \x1b[2m__typename\x1b[0m } }",
+            highlight(
+                "query q($w: Int) { things: stuff(id: \"a\\\"b\", first: -10) { id __typename } }"
+            )
+        );
+
+        assert_eq!("{\n  \"a\": 1\n}", variables("{\"a\":1}"));
+        assert_eq!("{\"a\":", variables("{\"a\":"));
+    }
+}
@@ -0,0 +1,276 @@
+//! Export query counts and latencies in the Prometheus text format so
+//! that query performance can be monitored from the logs alone. We keep
+//! counters and latency histograms for each subgraph and for each shape.
+//!
+//! Every shape becomes a separate set of series, which can overwhelm
+//! Prometheus. Once we have seen `max_shapes` shapes, entries for any new
+//! shape are counted under the shape `other` of their subgraph
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Response, Server};
+
+use crate::summary::SLOW_THRESHOLD;
+use crate::{Entry, QueryInfo};
+
+/// The number of shapes we export unless told otherwise
+pub const DEFAULT_MAX_SHAPES: usize = 500;
+
+/// The label for all shapes we do not export individually
+pub const OTHER_SHAPE: &str = "other";
+
+/// The upper bounds (in ms) of the latency histogram buckets
+const BUCKETS: &[u64] = &[5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+
+/// Counters and a latency histogram for one subgraph or shape
+#[derive(Debug, Default)]
+struct Series {
+    calls: u64,
+    slow: u64,
+    /// The total time in ms
+    time: u64,
+    /// The number of entries that fall into each of `BUCKETS`; this is
+    /// not cumulative
+    buckets: [u64; BUCKETS.len()],
+}
+
+impl Series {
+    fn add(&mut self, time: u64) {
+        self.calls += 1;
+        self.time += time;
+        if time > SLOW_THRESHOLD {
+            self.slow += 1;
+        }
+        if let Some(pos) = BUCKETS.iter().position(|bound| time <= *bound) {
+            self.buckets[pos] += 1;
+        }
+    }
+
+    fn write_histogram(&self, out: &mut String, name: &str, labels: &str) {
+        let mut count = 0;
+        for (bound, n) in BUCKETS.iter().zip(self.buckets.iter()) {
+            count += n;
+            let le = *bound as f64 / 1000.0;
+            writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, le, count).unwrap();
+        }
+        writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.calls
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{}_sum{{{}}} {}",
+            name,
+            labels,
+            self.time as f64 / 1000.0
+        )
+        .unwrap();
+        writeln!(out, "{}_count{{{}}} {}", name, labels, self.calls).unwrap();
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+/// Maintains the metrics for all entries passed to `add`
+#[derive(Debug)]
+pub struct Exporter {
+    subgraphs: BTreeMap<String, Series>,
+    /// Keyed by subgraph and shape hash, or `OTHER_SHAPE`
+    shapes: BTreeMap<(String, String), Series>,
+    /// The number of distinct shapes in `shapes`, not counting
+    /// `OTHER_SHAPE`
+    distinct: usize,
+    max_shapes: usize,
+    resolve_variables: bool,
+}
+
+impl Exporter {
+    pub fn new(max_shapes: usize) -> Self {
+        Exporter {
+            subgraphs: BTreeMap::new(),
+            shapes: BTreeMap::new(),
+            distinct: 0,
+            max_shapes,
+            resolve_variables: false,
+        }
+    }
+
+    /// Label shapes with `QueryInfo::resolved_hash` so that they match the
+    /// hashes in summaries that were produced that way
+    pub fn resolve_variables(&mut self, resolve: bool) {
+        self.resolve_variables = resolve;
+    }
+
+    pub fn add(&mut self, entry: &Entry) {
+        self.subgraphs
+            .entry(entry.subgraph.to_string())
+            .or_default()
+            .add(entry.time);
+
+        let hash = QueryInfo::entry_hash(entry, self.resolve_variables);
+        let mut key = (entry.subgraph.to_string(), hash.to_string());
+        if !self.shapes.contains_key(&key) {
+            if self.distinct < self.max_shapes {
+                self.distinct += 1;
+            } else {
+                key.1 = OTHER_SHAPE.to_owned();
+            }
+        }
+        self.shapes.entry(key).or_default().add(entry.time);
+    }
+
+    /// The metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            (
+                "qlog_queries_total",
+                "Number of queries",
+                (|s: &Series| s.calls) as fn(&Series) -> u64,
+            ),
+            (
+                "qlog_slow_queries_total",
+                "Number of queries that took longer than 1s",
+                |s: &Series| s.slow,
+            ),
+        ];
+        for (name, help, value) in &counters {
+            header(&mut out, name, "counter", help);
+            for (subgraph, series) in &self.subgraphs {
+                writeln!(
+                    out,
+                    "{}{{subgraph=\"{}\"}} {}",
+                    name,
+                    escape(subgraph),
+                    value(series)
+                )
+                .unwrap();
+            }
+        }
+        let name = "qlog_query_duration_seconds";
+        header(&mut out, name, "histogram", "Time it took to run queries");
+        for (subgraph, series) in &self.subgraphs {
+            let labels = format!("subgraph=\"{}\"", escape(subgraph));
+            series.write_histogram(&mut out, name, &labels);
+        }
+
+        for (name, help, value) in &counters {
+            let name = name.replace("qlog_", "qlog_shape_");
+            header(&mut out, &name, "counter", &format!("{} per shape", help));
+            for ((subgraph, shape), series) in &self.shapes {
+                writeln!(
+                    out,
+                    "{}{{subgraph=\"{}\",shape=\"{}\"}} {}",
+                    name,
+                    escape(subgraph),
+                    shape,
+                    value(series)
+                )
+                .unwrap();
+            }
+        }
+        let name = "qlog_shape_query_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Time it took to run queries per shape",
+        );
+        for ((subgraph, shape), series) in &self.shapes {
+            let labels = format!("subgraph=\"{}\",shape=\"{}\"", escape(subgraph), shape);
+            series.write_histogram(&mut out, name, &labels);
+        }
+        out
+    }
+}
+
+/// Serve the metrics of `exporter` on `addr` at `/metrics` until there is
+/// an error
+pub fn serve(addr: &str, exporter: Arc<Mutex<Exporter>>) -> Result<(), io::Error> {
+    let server = Server::http(addr).map_err(|e| io::Error::other(e.to_string()))?;
+    let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+    for request in server.incoming_requests() {
+        let path = request.url().split('?').next().unwrap_or("");
+        let response = if path == "/metrics" {
+            let body = exporter.lock().unwrap().render();
+            Response::from_string(body).with_header(content_type.clone())
+        } else {
+            Response::from_string("not found\n").with_status_code(404)
+        };
+        request.respond(response)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::fixtures::entry;
+
+    #[test]
+    fn render_metrics() {
+        let mut exporter = Exporter::new(1);
+        exporter.add(&entry("a", 20));
+        exporter.add(&entry("a", 1500));
+        exporter.add(&entry("b", 40000));
+        let text = exporter.render();
+        let lines: Vec<_> = text.lines().collect();
+
+        let has = |line: &str| lines.contains(&line);
+        assert!(has("qlog_queries_total{subgraph=\"QmSuBgRaPh\"} 3"));
+        assert!(has("qlog_slow_queries_total{subgraph=\"QmSuBgRaPh\"} 2"));
+        assert!(has(
+            "qlog_query_duration_seconds_bucket{subgraph=\"QmSuBgRaPh\",le=\"0.025\"} 1"
+        ));
+        assert!(has(
+            "qlog_query_duration_seconds_bucket{subgraph=\"QmSuBgRaPh\",le=\"30\"} 2"
+        ));
+        assert!(has(
+            "qlog_query_duration_seconds_bucket{subgraph=\"QmSuBgRaPh\",le=\"+Inf\"} 3"
+        ));
+        assert!(has(
+            "qlog_query_duration_seconds_sum{subgraph=\"QmSuBgRaPh\"} 41.52"
+        ));
+
+        // Only the first shape gets its own label
+        let shape = QueryInfo::hash("f2-6b-48-b6-6b", "{ a { id } }", "QmSuBgRaPh");
+        assert!(has(&format!(
+            "qlog_shape_queries_total{{subgraph=\"QmSuBgRaPh\",shape=\"{}\"}} 2",
+            shape
+        )));
+        assert!(has(
+            "qlog_shape_queries_total{subgraph=\"QmSuBgRaPh\",shape=\"other\"} 1"
+        ));
+        assert_eq!(
+            2,
+            lines
+                .iter()
+                .filter(|line| line.starts_with("qlog_shape_queries_total"))
+                .count()
+        );
+
+        // Shapes match the summaries written with --resolve-variables
+        let mut exporter = Exporter::new(1);
+        exporter.resolve_variables(true);
+        exporter.add(&entry("a", 20));
+        let shape = QueryInfo::resolved_hash("{ a { id } }", "{}", "QmSuBgRaPh");
+        assert!(exporter.render().contains(&format!(
+            "qlog_shape_queries_total{{subgraph=\"QmSuBgRaPh\",shape=\"{}\"}} 1",
+            shape
+        )));
+    }
+}
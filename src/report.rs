@@ -0,0 +1,348 @@
+//! Render summaries as a self-contained HTML page that can be sent to
+//! subgraph developers. The page has overall totals, the totals for each
+//! subgraph, the top shapes by total time, average time and slow queries,
+//! and details for each of those shapes. Charts are inline SVG, and the
+//! page does not load anything from the network
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::pretty;
+use crate::summary::SLOW_THRESHOLD;
+use crate::{rollup, sort_summaries, QueryInfo};
+
+/// How many shapes each of the top lists has unless told otherwise
+pub const DEFAULT_TOP: usize = 20;
+
+/// How many bars a chart has at most
+const CHART_BARS: usize = 10;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 70em; color: #222; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { padding: 0.2em 0.8em; border-bottom: 1px solid #ddd; }
+th { text-align: left; background: #f4f4f4; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+pre { background: #f8f8f8; padding: 0.8em; overflow-x: auto; }
+.shape { border-top: 2px solid #ccc; margin-top: 2em; }
+svg text { font-size: 12px; }
+";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Format a time in ms so that it is easy to read
+fn duration(ms: u64) -> String {
+    let secs = ms as f64 / 1000.0;
+    if secs >= 2.0 * 3600.0 {
+        format!("{:.1} h", secs / 3600.0)
+    } else if secs >= 300.0 {
+        format!("{:.1} m", secs / 60.0)
+    } else if secs >= 10.0 {
+        format!("{:.1} s", secs)
+    } else {
+        format!("{} ms", ms)
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+/// A horizontal bar chart with one bar for each `(label, link, value)`;
+/// the value is shown with `format`
+fn bar_chart(out: &mut String, bars: &[(String, String, u64)], format: fn(u64) -> String) {
+    const WIDTH: u64 = 700;
+    const LABEL: u64 = 160;
+    const BAR: u64 = 22;
+
+    let max = bars
+        .iter()
+        .map(|(_, _, value)| *value)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let height = BAR * bars.len() as u64;
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">",
+        WIDTH, height
+    )
+    .unwrap();
+    for (indx, (label, link, value)) in bars.iter().enumerate() {
+        let y = BAR * indx as u64;
+        let width = (WIDTH - LABEL - 90) * value / max;
+        writeln!(
+            out,
+            "<a href=\"{link}\"><text x=\"0\" y=\"{ty}\">{label}</text></a>\
+             <rect x=\"{x}\" y=\"{y}\" width=\"{width}\" height=\"{h}\" fill=\"#4e79a7\"/>\
+             <text x=\"{vx}\" y=\"{ty}\">{value}</text>",
+            link = escape(link),
+            ty = y + 15,
+            label = escape(&shorten(label, 22)),
+            x = LABEL,
+            y = y + 3,
+            width = width,
+            h = BAR - 6,
+            vx = LABEL + width + 5,
+            value = format(*value),
+        )
+        .unwrap();
+    }
+    out.push_str("</svg>\n");
+}
+
+fn shorten(text: &str, len: usize) -> String {
+    if text.chars().count() <= len {
+        text.to_owned()
+    } else {
+        let text: String = text.chars().take(len - 1).collect();
+        format!("{}…", text)
+    }
+}
+
+/// A table of `infos` with links to their details
+fn shape_table(out: &mut String, infos: &[QueryInfo]) {
+    out.push_str(
+        "<table><tr><th>QID</th><th>subgraph</th><th>calls</th><th>total</th>\
+         <th>avg</th><th>max</th><th>slow</th></tr>\n",
+    );
+    for info in infos {
+        writeln!(
+            out,
+            "<tr><td><a href=\"#Q{id}\">Q{id}</a></td><td>{}</td><td class=\"num\">{}</td>\
+             <td class=\"num\">{}</td><td class=\"num\">{:.0} ms</td>\
+             <td class=\"num\">{} ms</td><td class=\"num\">{}</td></tr>",
+            escape(&info.subgraph),
+            info.calls,
+            duration(info.total_time),
+            info.avg(),
+            info.max_time,
+            info.slow_count,
+            id = info.id,
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+}
+
+/// Everything we know about `info`, including its query and the
+/// variables of its slowest invocation
+fn shape_details(out: &mut String, info: &QueryInfo) {
+    writeln!(
+        out,
+        "<div class=\"shape\" id=\"Q{}\"><h3>Q{}</h3>",
+        info.id, info.id
+    )
+    .unwrap();
+    out.push_str("<table>\n");
+    let mut row = |name: &str, value: String| {
+        writeln!(
+            out,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape(name),
+            escape(&value)
+        )
+        .unwrap();
+    };
+    row("subgraph", info.subgraph.clone());
+    for (dim, value) in &info.groups {
+        row(dim, value.clone());
+    }
+    row("calls", info.calls.to_string());
+    row("total time", duration(info.total_time));
+    row("average time", format!("{:.0} ms", info.avg()));
+    row("standard deviation", format!("{:.0} ms", info.stddev()));
+    row("max time", format!("{} ms", info.max_time));
+    row(
+        "slow queries",
+        format!(
+            "{} ({:.2} %)",
+            info.slow_count,
+            percent(info.slow_count, info.calls)
+        ),
+    );
+    row("complexity", info.max_complexity.to_string());
+    row("depth", info.depth.to_string());
+    row("max_uuid", info.max_uuid.clone());
+    out.push_str("</table>\n");
+    writeln!(
+        out,
+        "<h4>Query</h4>\n<pre>{}</pre>\n<h4>Variables of the slowest query</h4>\n<pre>{}</pre>\n</div>",
+        escape(&pretty::query(&info.query)),
+        escape(&pretty::variables(&info.max_variables))
+    )
+    .unwrap();
+}
+
+/// The HTML report for `infos`, with `top` shapes in each of the top
+/// lists
+pub fn html(infos: &[QueryInfo], title: &str, top: usize) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>{}</style>\n</head>\n<body>\n<h1>{}</h1>",
+        escape(title),
+        STYLE,
+        escape(title)
+    )
+    .unwrap();
+
+    let subgraphs = rollup(infos);
+    let calls: u64 = infos.iter().map(|info| info.calls).sum();
+    let total_time: u64 = infos.iter().map(|info| info.total_time).sum();
+    let slow_count: u64 = infos.iter().map(|info| info.slow_count).sum();
+    out.push_str("<h2>Totals</h2>\n<table>\n");
+    let totals = [
+        ("subgraphs", subgraphs.len().to_string()),
+        ("query shapes", infos.len().to_string()),
+        ("queries", calls.to_string()),
+        ("total time", duration(total_time)),
+        (
+            "average time",
+            format!("{:.0} ms", total_time as f64 / calls.max(1) as f64),
+        ),
+        (
+            "slow queries",
+            format!(
+                "{} ({:.2} %) took longer than {} ms",
+                slow_count,
+                percent(slow_count, calls),
+                SLOW_THRESHOLD
+            ),
+        ),
+    ];
+    for (name, value) in &totals {
+        writeln!(out, "<tr><th>{}</th><td>{}</td></tr>", name, escape(value)).unwrap();
+    }
+    out.push_str("</table>\n");
+
+    out.push_str("<h2>Subgraphs</h2>\n");
+    let bars: Vec<_> = subgraphs
+        .iter()
+        .take(CHART_BARS)
+        .map(|sg| {
+            (
+                sg.subgraph.clone(),
+                format!("#{}", sg.subgraph),
+                sg.total_time,
+            )
+        })
+        .collect();
+    bar_chart(&mut out, &bars, duration);
+    out.push_str(
+        "<table><tr><th>subgraph</th><th>shapes</th><th>calls</th><th>total</th>\
+         <th>avg</th><th>max</th><th>slow</th></tr>\n",
+    );
+    for sg in &subgraphs {
+        writeln!(
+            out,
+            "<tr id=\"{sg}\"><td>{sg}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+             <td class=\"num\">{}</td><td class=\"num\">{:.0} ms</td>\
+             <td class=\"num\">{} ms</td><td class=\"num\">{}</td></tr>",
+            sg.shapes,
+            sg.calls,
+            duration(sg.total_time),
+            sg.total_time as f64 / sg.calls.max(1) as f64,
+            sg.max_time,
+            sg.slow_count,
+            sg = escape(&sg.subgraph),
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+
+    // The shapes we show details for, in the order of the top lists
+    let mut detailed = Vec::new();
+    let mut seen = BTreeSet::new();
+    let lists = [
+        ("total_time", "Top shapes by total time"),
+        ("avg", "Top shapes by average time"),
+        ("slow", "Top shapes by slow queries"),
+    ];
+    for (column, heading) in &lists {
+        let mut shapes = infos.to_vec();
+        sort_summaries(&mut shapes, column);
+        let shapes: Vec<_> = shapes
+            .into_iter()
+            .filter(|info| *column != "slow" || info.slow_count > 0)
+            .take(top)
+            .collect();
+        writeln!(out, "<h2>{}</h2>", heading).unwrap();
+        if shapes.is_empty() {
+            out.push_str("<p>None</p>\n");
+            continue;
+        }
+        if *column == "total_time" {
+            let bars: Vec<_> = shapes
+                .iter()
+                .take(CHART_BARS)
+                .map(|info| {
+                    let id = format!("Q{}", info.id);
+                    (id.clone(), format!("#{}", id), info.total_time)
+                })
+                .collect();
+            bar_chart(&mut out, &bars, duration);
+        }
+        shape_table(&mut out, &shapes);
+        for info in shapes {
+            if seen.insert(info.id) {
+                detailed.push(info);
+            }
+        }
+    }
+
+    out.push_str("<h2>Shape details</h2>\n");
+    for info in &detailed {
+        shape_details(&mut out, info);
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::fixtures::info;
+
+    #[test]
+    fn render_report() {
+        let mut infos = vec![
+            info(
+                1,
+                "QmA",
+                "{ things(where: { a: \"<b>\" }) { id } }",
+                10,
+                100,
+            ),
+            info(2, "QmA", "{ stuff { id } }", 10, 30000),
+            info(3, "QmB", "{ other { id } }", 10, 500),
+        ];
+        infos[1].slow_count = 3;
+        for info in &mut infos {
+            info.max_variables = "{\"a\":\"<b>\"}".to_owned();
+        }
+        let page = html(&infos, "Weekly report", 2);
+
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<tr><th>queries</th><td>30</td></tr>"));
+        // Q1 is neither in the top 2 by total nor by average time
+        assert!(page.contains("id=\"Q2\""));
+        assert!(page.contains("id=\"Q3\""));
+        assert!(!page.contains("id=\"Q1\""));
+        assert!(page.contains("<pre>{\n  stuff {\n    id\n  }\n}\n</pre>"));
+        assert!(page.contains("&quot;a&quot;: &quot;&lt;b&gt;&quot;"));
+        assert!(!page.contains("<b>"));
+        // Nothing is loaded from elsewhere
+        assert!(!page.contains("src="));
+        assert!(!page.contains("<link"));
+    }
+}
@@ -19,6 +19,8 @@ struct Sample {
     time: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
 }
 
 impl<'a> From<&Entry<'a>> for Sample {
@@ -30,11 +32,13 @@ impl<'a> From<&Entry<'a>> for Sample {
             block: entry.block,
             time: entry.time,
             timestamp: entry.timestamp.as_ref().map(|s| s.to_string()),
+            origin: entry.origin.as_ref().map(|s| s.to_string()),
         }
     }
 }
 
 /// A collection of query samples; we use one of these for each subgraph.
+#[derive(Default)]
 struct SampleDomain {
     /// The total number of unique queries we have seen
     seen_count: usize,
@@ -44,16 +48,6 @@ struct SampleDomain {
     samples: Vec<Sample>,
 }
 
-impl Default for SampleDomain {
-    fn default() -> Self {
-        SampleDomain {
-            seen_count: 0,
-            seen: HashSet::default(),
-            samples: Vec::default(),
-        }
-    }
-}
-
 impl SampleDomain {
     /// If we have not seen `(query, variables)` before, add them to our samples
     /// so that in the end the probability that any unique query is in our
@@ -106,8 +100,9 @@ impl Sampler {
         }
     }
 
-    pub fn sample<'b>(&mut self, entry: &Entry) {
+    pub fn sample(&mut self, entry: &Entry) {
         if self.size == 0
+            || entry.truncated
             || entry.subgraph == INDEX_NODE_SUBGRAPH
             || entry.subgraph == SUBGRAPHS_SUBGRAPH
             || (!self.subgraphs.is_empty() && !self.subgraphs.contains(entry.subgraph.as_ref()))
@@ -126,25 +121,43 @@ impl Sampler {
     }
 
     pub fn write(&mut self) -> Result<(), std::io::Error> {
-        if self.size <= 0 {
+        if self.size == 0 {
+            return Ok(());
+        }
+        write_samples(&self.samples, &mut self.out)?;
+        self.out.flush()
+    }
+
+    /// Write the samples we have so far to `out` rather than the file
+    /// this sampler was created with
+    pub fn write_to(&self, out: &mut dyn Write) -> Result<(), std::io::Error> {
+        if self.size == 0 {
             return Ok(());
         }
+        write_samples(&self.samples, out)
+    }
+}
 
-        for (subgraph, domain) in &self.samples {
-            for sample in &domain.samples {
-                let subgraph = Cow::from(subgraph);
-                let entry = Entry {
-                    subgraph,
-                    query_id: Cow::from(&sample.query_id),
-                    block: sample.block,
-                    time: sample.time,
-                    query: Cow::from(&sample.query),
-                    variables: Cow::from(&sample.variables),
-                    timestamp: sample.timestamp.as_ref().map(|s| Cow::from(s)),
-                };
-                writeln!(self.out, "{}", serde_json::to_string(&entry)?)?;
-            }
+fn write_samples(
+    samples: &BTreeMap<String, SampleDomain>,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    for (subgraph, domain) in samples {
+        for sample in &domain.samples {
+            let subgraph = Cow::from(subgraph);
+            let entry = Entry {
+                subgraph,
+                query_id: Cow::from(&sample.query_id),
+                block: sample.block,
+                time: sample.time,
+                query: Cow::from(&sample.query),
+                variables: Cow::from(&sample.variables),
+                timestamp: sample.timestamp.as_ref().map(Cow::from),
+                truncated: false,
+                origin: sample.origin.as_ref().map(Cow::from),
+            };
+            writeln!(out, "{}", serde_json::to_string(&entry)?)?;
         }
-        Ok(())
     }
+    Ok(())
 }
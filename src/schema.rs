@@ -0,0 +1,470 @@
+//! Analyze queries with the help of the GraphQL schema of their subgraph.
+//! With the schema, we know which entity types a query touches, which
+//! fields are lists and which are derived, and can check whether a query
+//! would pass validation at all.
+//!
+//! Schemas are the `schema.graphql` files that subgraphs are deployed
+//! with. graph-node generates the `Query` type from them; we recreate its
+//! fields from the entity types and `@fulltext` directives
+use graphql_parser::query as q;
+use graphql_parser::schema as s;
+use inflector::Inflector;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::QueryInfo;
+
+/// What we learned about a query from the schema of its subgraph
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchemaInfo {
+    /// The entity types the query touches
+    pub entities: BTreeSet<String>,
+    /// The number of fields that return a list of entities
+    pub lists: u64,
+    /// The number of fields that return a single entity
+    pub singles: u64,
+    /// The number of `@derivedFrom` fields the query traverses
+    pub derived: u64,
+    /// Why the query would fail validation; empty if it is valid
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct FieldType {
+    /// The name of the type without any list or non-null wrappers
+    name: String,
+    list: bool,
+    derived: bool,
+}
+
+impl FieldType {
+    fn new(field_type: &s::Type, derived: bool) -> Self {
+        let mut list = false;
+        let mut typ = field_type;
+        loop {
+            match typ {
+                s::Type::NamedType(name) => {
+                    return FieldType {
+                        name: name.clone(),
+                        list,
+                        derived,
+                    }
+                }
+                s::Type::ListType(inner) => {
+                    list = true;
+                    typ = inner;
+                }
+                s::Type::NonNullType(inner) => typ = inner,
+            }
+        }
+    }
+}
+
+/// The types of a subgraph and the fields they have
+#[derive(Debug, Default)]
+pub struct Schema {
+    /// Object types and interfaces, including the generated `Query` type
+    types: HashMap<String, HashMap<String, FieldType>>,
+    /// For interfaces and unions, the types that belong to them
+    members: HashMap<String, BTreeSet<String>>,
+}
+
+impl Schema {
+    pub fn parse(text: &str) -> Result<Schema, String> {
+        let doc = s::parse_schema(text).map_err(|e| e.to_string())?;
+        let mut schema = Schema::default();
+        let mut query = HashMap::new();
+
+        let fields = |fields: &[s::Field]| -> HashMap<String, FieldType> {
+            fields
+                .iter()
+                .map(|field| {
+                    let derived = field.directives.iter().any(|d| d.name == "derivedFrom");
+                    (
+                        field.name.clone(),
+                        FieldType::new(&field.field_type, derived),
+                    )
+                })
+                .collect()
+        };
+        // graph-node names the root fields with the same inflector, e.g.,
+        // `ERC20Token` becomes `erc20Token` and `erc20Tokens`. Its
+        // irregular plurals only apply to lowercase names, so `Person`
+        // becomes `persons`
+        fn add_root(query: &mut HashMap<String, FieldType>, name: &str) {
+            let typ = |list| FieldType {
+                name: name.to_owned(),
+                list,
+                derived: false,
+            };
+            query.insert(name.to_plural().to_camel_case(), typ(true));
+            query.insert(name.to_camel_case(), typ(false));
+        }
+
+        for defn in &doc.definitions {
+            use s::TypeDefinition::*;
+            let defn = match defn {
+                s::Definition::TypeDefinition(defn) => defn,
+                _ => continue,
+            };
+            match defn {
+                Object(obj) if obj.name == "_Schema_" => {
+                    // Fulltext search fields are declared as directives on
+                    // the fake `_Schema_` type
+                    for dir in obj.directives.iter().filter(|d| d.name == "fulltext") {
+                        let arg = |name| dir.arguments.iter().find(|(n, _)| n == name);
+                        let entity = arg("include").and_then(|(_, include)| match include {
+                            s::Value::List(items) => items.iter().find_map(|item| match item {
+                                s::Value::Object(obj) => match obj.get("entity") {
+                                    Some(s::Value::String(entity)) => Some(entity.clone()),
+                                    _ => None,
+                                },
+                                _ => None,
+                            }),
+                            _ => None,
+                        });
+                        if let (Some((_, s::Value::String(name))), Some(entity)) =
+                            (arg("name"), entity)
+                        {
+                            query.insert(
+                                name.clone(),
+                                FieldType {
+                                    name: entity,
+                                    list: true,
+                                    derived: false,
+                                },
+                            );
+                        }
+                    }
+                }
+                Object(obj) => {
+                    for iface in &obj.implements_interfaces {
+                        schema
+                            .members
+                            .entry(iface.clone())
+                            .or_default()
+                            .insert(obj.name.clone());
+                    }
+                    if obj.directives.iter().any(|d| d.name == "entity") {
+                        add_root(&mut query, &obj.name);
+                    }
+                    schema.types.insert(obj.name.clone(), fields(&obj.fields));
+                }
+                Interface(iface) => {
+                    add_root(&mut query, &iface.name);
+                    schema
+                        .types
+                        .insert(iface.name.clone(), fields(&iface.fields));
+                }
+                Union(union) => {
+                    schema
+                        .members
+                        .insert(union.name.clone(), union.types.iter().cloned().collect());
+                }
+                Scalar(_) | Enum(_) | InputObject(_) => {}
+            }
+        }
+        schema.types.insert("Query".to_owned(), query);
+        Ok(schema)
+    }
+
+    fn is_object(&self, name: &str) -> bool {
+        self.types.contains_key(name) || self.members.contains_key(name)
+    }
+
+    /// Whether a fragment on `cond` can be spread into a selection on
+    /// `typ`
+    fn applies(&self, cond: &str, typ: &str) -> bool {
+        let is_member = |group: &str, member: &str| {
+            self.members
+                .get(group)
+                .map(|members| members.contains(member))
+                .unwrap_or(false)
+        };
+        cond == typ || is_member(cond, typ) || is_member(typ, cond)
+    }
+
+    /// Analyze `doc` against this schema
+    pub fn analyze(&self, doc: &q::Document) -> SchemaInfo {
+        let fragments: HashMap<&str, &q::FragmentDefinition> = doc
+            .definitions
+            .iter()
+            .filter_map(|defn| match defn {
+                q::Definition::Fragment(frag) => Some((frag.name.as_str(), frag)),
+                q::Definition::Operation(_) => None,
+            })
+            .collect();
+        let mut analyzer = Analyzer {
+            schema: self,
+            fragments,
+            stack: Vec::new(),
+            info: SchemaInfo::default(),
+        };
+        for defn in &doc.definitions {
+            use q::OperationDefinition::*;
+            match defn {
+                q::Definition::Operation(SelectionSet(set)) => analyzer.walk(set, "Query"),
+                q::Definition::Operation(Query(query)) => {
+                    analyzer.walk(&query.selection_set, "Query")
+                }
+                q::Definition::Operation(Mutation(_)) => analyzer
+                    .info
+                    .errors
+                    .push("subgraphs do not support mutations".to_owned()),
+                q::Definition::Operation(Subscription(sub)) => {
+                    analyzer.walk(&sub.selection_set, "Query")
+                }
+                q::Definition::Fragment(_) => {}
+            }
+        }
+        analyzer.info
+    }
+}
+
+struct Analyzer<'a> {
+    schema: &'a Schema,
+    fragments: HashMap<&'a str, &'a q::FragmentDefinition>,
+    /// The fragments we are currently expanding
+    stack: Vec<&'a str>,
+    info: SchemaInfo,
+}
+
+impl<'a> Analyzer<'a> {
+    fn error(&mut self, msg: String) {
+        if !self.info.errors.contains(&msg) {
+            self.info.errors.push(msg);
+        }
+    }
+
+    fn walk(&mut self, set: &'a q::SelectionSet, typ: &str) {
+        for item in &set.items {
+            match item {
+                q::Selection::Field(field) => self.field(field, typ),
+                q::Selection::FragmentSpread(spread) => {
+                    let name = spread.fragment_name.as_str();
+                    if self.stack.contains(&name) {
+                        self.error(format!("fragment {} spreads itself", name));
+                        continue;
+                    }
+                    match self.fragments.get(name).copied() {
+                        Some(frag) => {
+                            let q::TypeCondition::On(cond) = &frag.type_condition;
+                            self.stack.push(name);
+                            self.fragment(cond, &frag.selection_set, typ);
+                            self.stack.pop();
+                        }
+                        None => self.error(format!("unknown fragment {}", name)),
+                    }
+                }
+                q::Selection::InlineFragment(frag) => match &frag.type_condition {
+                    Some(q::TypeCondition::On(cond)) => {
+                        self.fragment(cond, &frag.selection_set, typ)
+                    }
+                    None => self.walk(&frag.selection_set, typ),
+                },
+            }
+        }
+    }
+
+    fn fragment(&mut self, cond: &str, set: &'a q::SelectionSet, typ: &str) {
+        if !self.schema.is_object(cond) {
+            self.error(format!("unknown type {} in fragment", cond));
+        } else if !self.schema.applies(cond, typ) {
+            self.error(format!("fragment on {} can not be used on {}", cond, typ));
+        } else {
+            self.walk(set, cond);
+        }
+    }
+
+    fn field(&mut self, field: &'a q::Field, typ: &str) {
+        // `_meta` is the same for all subgraphs, and we do not check it
+        if field.name == "__typename" || (typ == "Query" && field.name == "_meta") {
+            return;
+        }
+        // Fields of interfaces and unions that are only defined on
+        // their members can only be selected through fragments
+        let field_type = match self
+            .schema
+            .types
+            .get(typ)
+            .and_then(|fields| fields.get(&field.name))
+        {
+            Some(field_type) => field_type,
+            None => {
+                return self.error(format!("{} has no field {}", typ, field.name));
+            }
+        };
+
+        if !self.schema.is_object(&field_type.name) {
+            if !field.selection_set.items.is_empty() {
+                self.error(format!(
+                    "{}.{} is a {} and can not have a selection",
+                    typ, field.name, field_type.name
+                ));
+            }
+            return;
+        }
+        if field.selection_set.items.is_empty() {
+            self.error(format!("{}.{} needs a selection", typ, field.name));
+        }
+        self.info.entities.insert(field_type.name.clone());
+        if field_type.list {
+            self.info.lists += 1;
+        } else {
+            self.info.singles += 1;
+        }
+        if field_type.derived {
+            self.info.derived += 1;
+        }
+        self.walk(&field.selection_set, &field_type.name);
+    }
+}
+
+/// The schemas of many subgraphs, keyed by subgraph id
+#[derive(Debug, Default)]
+pub struct Schemas {
+    schemas: HashMap<String, Schema>,
+}
+
+impl Schemas {
+    /// Load all files `<subgraph id>.graphql` in `dir`. Schemas that can
+    /// not be parsed are reported on stderr and skipped
+    pub fn load(dir: impl AsRef<Path>) -> io::Result<Schemas> {
+        let mut schemas = HashMap::new();
+        for file in fs::read_dir(dir)? {
+            let path = file?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("graphql") {
+                continue;
+            }
+            let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(id) => id.to_owned(),
+                None => continue,
+            };
+            match Schema::parse(&fs::read_to_string(&path)?) {
+                Ok(schema) => {
+                    schemas.insert(id, schema);
+                }
+                Err(e) => eprintln!("Failed to parse schema {}: {}", path.display(), e),
+            }
+        }
+        Ok(Schemas { schemas })
+    }
+
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    pub fn insert(&mut self, subgraph: String, schema: Schema) {
+        self.schemas.insert(subgraph, schema);
+    }
+
+    /// The entity type that the top-level field `field` of `subgraph`
+    /// returns, if we have the schema of the subgraph and it has the field
+    pub fn root_entity(&self, subgraph: &str, field: &str) -> Option<&str> {
+        let query = self.schemas.get(subgraph)?.types.get("Query")?;
+        query.get(field).map(|typ| typ.name.as_str())
+    }
+
+    /// Set `info.schema` if we have the schema for its subgraph and know
+    /// its query
+    pub fn annotate(&self, info: &mut QueryInfo) {
+        if info.is_truncated() {
+            return;
+        }
+        if let Some(schema) = self.schemas.get(&info.subgraph) {
+            info.schema = crate::shape_hash::parse(&info.query).map(|doc| schema.analyze(&doc));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graphql_parser::parse_query;
+
+    const SCHEMA: &str = "
+        type Transcoder @entity { id: ID! pools: [Pool!]! @derivedFrom(field: \"transcoder\") }
+        type Pool @entity { id: ID! transcoder: Transcoder! fees: BigInt! }
+        type Day @entity { id: ID! }
+        type _Schema_ @fulltext(name: \"poolSearch\", language: en, algorithm: rank,
+                                include: [{ entity: \"Pool\", fields: [{ name: \"id\" }] }])";
+
+    fn analyze(query: &str) -> SchemaInfo {
+        let schema = Schema::parse(SCHEMA).unwrap();
+        schema.analyze(&parse_query(query).unwrap())
+    }
+
+    #[test]
+    fn lists_and_derived_fields() {
+        let info = analyze(
+            "{ transcoders(first: 5) { id pools { id ... on Pool { fees } } } \
+               day(id: \"1\") { ...DayFields } _meta { block { number } } } \
+             fragment DayFields on Day { id }",
+        );
+        let expected = SchemaInfo {
+            entities: vec!["Day", "Pool", "Transcoder"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            lists: 2,
+            singles: 1,
+            derived: 1,
+            errors: vec![],
+        };
+        assert_eq!(expected, info);
+        assert!(analyze("{ poolSearch(text: \"x\") { id } days { id } }")
+            .errors
+            .is_empty());
+    }
+
+    #[test]
+    fn root_field_names() {
+        let schema = Schema::parse(
+            "type Buy @entity { id: ID! } type Analysis @entity { id: ID! } \
+             type ERC20Token @entity { id: ID! } type Person @entity { id: ID! }",
+        )
+        .unwrap();
+        let mut names: Vec<_> = schema.types["Query"].keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(
+            vec![
+                "analyses",
+                "analysis",
+                "buy",
+                "buys",
+                "erc20Token",
+                "erc20Tokens",
+                "person",
+                "persons"
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn invalid_queries() {
+        let info = analyze(
+            "{ transcoders { id name pools } \
+               pool(id: \"1\") { fees { id } ... on Day { id } } \
+               things { id } }",
+        );
+        assert_eq!(
+            vec![
+                "Transcoder has no field name",
+                "Transcoder.pools needs a selection",
+                "Pool.fees is a BigInt and can not have a selection",
+                "fragment on Day can not be used on Pool",
+                "Query has no field things",
+            ],
+            info.errors
+        );
+    }
+}
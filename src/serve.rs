@@ -0,0 +1,276 @@
+//! A small HTTP server that makes summaries available as a JSON API so
+//! that dashboards do not have to read summary files themselves. The
+//! summary files are reloaded whenever one of them changes.
+//!
+//! The API has these endpoints; all of them only accept `GET`:
+//!
+//! * `/shapes`: list summaries, sorted by `sort` (default `total_time`),
+//!   filtered by `subgraph`, `min_calls` and `search` (a substring of the
+//!   query), and paginated with `offset` and `limit`
+//! * `/shapes/Q<id>`: the summary with that id
+//! * `/shapes/<hash>`: all summaries for that shape hash, one for each
+//!   group if summaries were broken down with `--group-by`
+//! * `/subgraphs`: the totals for each subgraph, most expensive first
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::time::SystemTime;
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::{combine, read_summaries, rollup, sort_summaries, QueryInfo};
+
+/// How many summaries `/shapes` returns if the request does not say
+pub const DEFAULT_LIMIT: usize = 50;
+/// The most summaries `/shapes` returns, no matter what the request says
+pub const MAX_LIMIT: usize = 1000;
+
+/// The summaries from a list of files, and when we last read them
+pub struct Summaries {
+    files: Vec<String>,
+    modified: Vec<Option<SystemTime>>,
+    infos: Vec<QueryInfo>,
+}
+
+fn modified(files: &[String]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+impl Summaries {
+    /// Read the summaries from `files`. If there is more than one file,
+    /// their summaries are combined as with `qlog combine`
+    pub fn load(files: Vec<String>) -> Result<Self, io::Error> {
+        let mut summaries = Summaries {
+            modified: modified(&files),
+            files,
+            infos: Vec::new(),
+        };
+        summaries.infos = summaries.read()?;
+        Ok(summaries)
+    }
+
+    fn read(&self) -> Result<Vec<QueryInfo>, io::Error> {
+        let mut infos = Vec::new();
+        for file in &self.files {
+            let summaries = read_summaries(file)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file, e)))?;
+            infos.push(summaries);
+        }
+        if infos.len() == 1 {
+            Ok(infos.pop().unwrap())
+        } else {
+            Ok(combine(infos.into_iter().flatten()))
+        }
+    }
+
+    /// Reread the files if any of them changed since we last read them.
+    /// Return `true` if we did. If reading fails, we keep the summaries
+    /// we already have
+    pub fn refresh(&mut self) -> Result<bool, io::Error> {
+        let modified = modified(&self.files);
+        if modified == self.modified {
+            return Ok(false);
+        }
+        self.infos = self.read()?;
+        self.modified = modified;
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.infos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.infos.is_empty()
+    }
+}
+
+/// Undo the percent-encoding of a query parameter
+fn decode(s: &str) -> String {
+    let s = s.replace('+', " ");
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn error(status: u16, msg: &str) -> (u16, Value) {
+    (status, json!({ "error": msg }))
+}
+
+/// Answer a request for `url` from `infos` with a status code and the
+/// JSON body of the response
+fn respond(infos: &[QueryInfo], url: &str) -> (u16, Value) {
+    let (path, query) = match url.find('?') {
+        Some(pos) => (&url[..pos], &url[pos + 1..]),
+        None => (url, ""),
+    };
+    let params: BTreeMap<String, String> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(pos) => (decode(&pair[..pos]), decode(&pair[pos + 1..])),
+            None => (decode(pair), String::new()),
+        })
+        .collect();
+    let number = |name: &str, default: usize| -> Result<usize, (u16, Value)> {
+        match params.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| error(400, &format!("`{}` must be a number", name))),
+            None => Ok(default),
+        }
+    };
+
+    let segments: Vec<_> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match segments.as_slice() {
+        ["shapes"] => {
+            let (offset, limit, min_calls) = match (
+                number("offset", 0),
+                number("limit", DEFAULT_LIMIT),
+                number("min_calls", 0),
+            ) {
+                (Ok(offset), Ok(limit), Ok(min_calls)) => (offset, limit.min(MAX_LIMIT), min_calls),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return e,
+            };
+            let mut shapes: Vec<QueryInfo> = infos
+                .iter()
+                .filter(|info| {
+                    params
+                        .get("subgraph")
+                        .map_or(true, |subgraph| &info.subgraph == subgraph)
+                        && info.calls >= min_calls as u64
+                        && params
+                            .get("search")
+                            .map_or(true, |search| info.query.contains(search.as_str()))
+                })
+                .cloned()
+                .collect();
+            let sort = params.get("sort").map_or("total_time", |s| s.as_str());
+            sort_summaries(&mut shapes, sort);
+            let total = shapes.len();
+            let shapes: Vec<_> = shapes.into_iter().skip(offset).take(limit).collect();
+            (
+                200,
+                json!({ "total": total, "offset": offset, "limit": limit, "shapes": shapes }),
+            )
+        }
+        ["shapes", id] => {
+            if let Some(qid) = id.strip_prefix('Q') {
+                let qid: usize = match qid.parse() {
+                    Ok(qid) => qid,
+                    Err(_) => return error(400, &format!("invalid query id {}", id)),
+                };
+                match infos.iter().find(|info| info.id == qid) {
+                    Some(info) => (200, json!(info)),
+                    None => error(404, &format!("no query with id {}", id)),
+                }
+            } else {
+                let hash: u64 = match id.parse() {
+                    Ok(hash) => hash,
+                    Err(_) => return error(400, &format!("invalid shape hash {}", id)),
+                };
+                let shapes: Vec<_> = infos.iter().filter(|info| info.hash == hash).collect();
+                if shapes.is_empty() {
+                    error(404, &format!("no shape with hash {}", id))
+                } else {
+                    (200, json!(shapes))
+                }
+            }
+        }
+        ["subgraphs"] => (200, json!(rollup(infos))),
+        _ => error(404, &format!("unknown path {}", path)),
+    }
+}
+
+/// Serve the API on `addr` until there is an error. Before answering a
+/// request, `summaries` is refreshed if any of its files changed
+pub fn serve(addr: &str, mut summaries: Summaries) -> Result<(), io::Error> {
+    let server = Server::http(addr).map_err(|e| io::Error::other(e.to_string()))?;
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    for request in server.incoming_requests() {
+        match summaries.refresh() {
+            Ok(true) => eprintln!("Reloaded {} summaries", summaries.len()),
+            Ok(false) => {}
+            Err(e) => eprintln!("Keeping old summaries since reloading failed: {}", e),
+        }
+        let (status, body) = if request.method() == &Method::Get {
+            respond(&summaries.infos, request.url())
+        } else {
+            error(405, "only GET requests are supported")
+        };
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(content_type.clone());
+        request.respond(response)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::fixtures::info;
+
+    #[test]
+    fn api() {
+        let infos = vec![
+            info(1, "QmA", "{ things1 { id } }", 10, 100),
+            info(2, "QmA", "{ things2 { id } }", 1, 300),
+            info(3, "QmB", "{ things3 { id } }", 5, 200),
+        ];
+        let ids = |body: &Value| -> Vec<u64> {
+            body["shapes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|shape| shape["id"].as_u64().unwrap())
+                .collect()
+        };
+
+        let (status, body) = respond(&infos, "/shapes");
+        assert_eq!(200, status);
+        assert_eq!(vec![2, 3, 1], ids(&body));
+        assert_eq!(3, body["total"]);
+
+        let (_, body) = respond(&infos, "/shapes?sort=calls&offset=1&limit=1");
+        assert_eq!(vec![3], ids(&body));
+        let (_, body) = respond(&infos, "/shapes?subgraph=QmA&min_calls=5");
+        assert_eq!(vec![1], ids(&body));
+        let (_, body) = respond(&infos, "/shapes?search=things3+%7B");
+        assert_eq!(vec![3], ids(&body));
+        assert_eq!(400, respond(&infos, "/shapes?limit=many").0);
+
+        let (status, body) = respond(&infos, "/shapes/Q2");
+        assert_eq!((200, 300), (status, body["total_time"].as_u64().unwrap()));
+        let (status, body) = respond(&infos, &format!("/shapes/{}", infos[2].hash));
+        assert_eq!((200, 1), (status, body.as_array().unwrap().len()));
+        assert_eq!(404, respond(&infos, "/shapes/Q7").0);
+
+        let (_, body) = respond(&infos, "/subgraphs");
+        assert_eq!("QmA", body[0]["subgraph"]);
+        assert_eq!(
+            (2, 11),
+            (
+                body[0]["shapes"].as_u64().unwrap(),
+                body[0]["calls"].as_u64().unwrap()
+            )
+        );
+        assert_eq!(404, respond(&infos, "/nothing").0);
+    }
+}
@@ -6,7 +6,9 @@
 
 use graphql_parser::query as q;
 use graphql_parser::schema as s;
+use serde_json::{Map, Value};
 use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
 
 type ShapeHasher = DefaultHasher;
@@ -15,6 +17,83 @@ pub trait ShapeHash {
     fn shape_hash(&self, hasher: &mut ShapeHasher);
 }
 
+/// Parse `query`, printing an error message if that fails
+pub fn parse(query: &str) -> Option<q::Document> {
+    graphql_parser::parse_query(query)
+        .map_err(|e| eprintln!("Failed to parse GraphQL query: {}: {}", e, query))
+        .ok()
+}
+
+/// Replace all variables in `doc` with their values from `variables`,
+/// which must be the JSON text of an object. Since the shape hash ignores
+/// variables, but not the keys of objects, this makes a query that passes
+/// e.g. a whole `where` filter in a variable hash the same as the query
+/// with the filter written inline. Variables that `variables` does not
+/// mention are left alone
+pub fn substitute(doc: &mut q::Document, variables: &str) {
+    fn to_value(json: &Value) -> s::Value {
+        match json {
+            Value::Null => s::Value::Null,
+            Value::Bool(b) => s::Value::Boolean(*b),
+            Value::Number(n) => match n.as_i64().map(i32::try_from) {
+                Some(Ok(i)) => s::Value::Int(i.into()),
+                _ => s::Value::Float(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(text) => s::Value::String(text.clone()),
+            Value::Array(values) => s::Value::List(values.iter().map(to_value).collect()),
+            Value::Object(map) => s::Value::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), to_value(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn resolve(value: &mut s::Value, vars: &Map<String, Value>) {
+        match value {
+            s::Value::Variable(name) => {
+                if let Some(json) = vars.get(name.as_str()) {
+                    *value = to_value(json);
+                }
+            }
+            s::Value::List(values) => values.iter_mut().for_each(|v| resolve(v, vars)),
+            s::Value::Object(map) => map.values_mut().for_each(|v| resolve(v, vars)),
+            _ => {}
+        }
+    }
+
+    fn selection_set(set: &mut q::SelectionSet, vars: &Map<String, Value>) {
+        for item in &mut set.items {
+            match item {
+                q::Selection::Field(field) => {
+                    for (_, v) in &mut field.arguments {
+                        resolve(v, vars);
+                    }
+                    selection_set(&mut field.selection_set, vars);
+                }
+                q::Selection::FragmentSpread(_) => {}
+                q::Selection::InlineFragment(frag) => selection_set(&mut frag.selection_set, vars),
+            }
+        }
+    }
+
+    let vars = match serde_json::from_str(variables) {
+        Ok(Value::Object(vars)) => vars,
+        _ => return,
+    };
+    for defn in &mut doc.definitions {
+        use q::OperationDefinition::*;
+        let set = match defn {
+            q::Definition::Operation(SelectionSet(set)) => set,
+            q::Definition::Operation(Query(query)) => &mut query.selection_set,
+            q::Definition::Operation(Mutation(mutation)) => &mut mutation.selection_set,
+            q::Definition::Operation(Subscription(subscription)) => &mut subscription.selection_set,
+            q::Definition::Fragment(frag) => &mut frag.selection_set,
+        };
+        selection_set(set, &vars);
+    }
+}
+
 pub fn shape_hash(query: &q::Document) -> u64 {
     let mut hasher = DefaultHasher::new();
     query.shape_hash(&mut hasher);
@@ -169,4 +248,21 @@ mod tests {
         assert_ne!(shape_hash(&q1), shape_hash(&q3));
         assert_ne!(shape_hash(&q2), shape_hash(&q4));
     }
+
+    #[test]
+    fn substitute_variables() {
+        const Q1: &str = "query things($where: Thing_filter) { things(where: $where) { id } }";
+        const Q2: &str = "{ things(where: { stuff_gt: 42 }) { id } }";
+        let mut q1 = parse_query(Q1).expect("q1 is syntactically valid");
+        let q2 = parse_query(Q2).expect("q2 is syntactically valid");
+        let unresolved = shape_hash(&q1);
+
+        substitute(&mut q1, "{\"where\":{\"stuff_gt\":7}}");
+        assert_eq!(shape_hash(&q1), shape_hash(&q2));
+        assert_ne!(unresolved, shape_hash(&q1));
+
+        let mut q3 = parse_query(Q1).expect("q1 is syntactically valid");
+        substitute(&mut q3, "{\"where\":{\"stuff_lte\":7}}");
+        assert_ne!(shape_hash(&q1), shape_hash(&q3));
+    }
 }
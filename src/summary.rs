@@ -0,0 +1,764 @@
+//! Summary statistics about queries, aggregated by the shape of the
+//! query and any additional grouping dimensions
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::group::Grouping;
+use crate::schema::SchemaInfo;
+use crate::{columnar, compact, metrics, shape_hash};
+use crate::{Entry, EntryParser};
+
+/// Queries that take longer than this (in ms) are considered slow
+pub const SLOW_THRESHOLD: u64 = 1000;
+
+/// The query of summaries that have only seen truncated entries
+pub const TRUNCATED_QUERY: &str = "(truncated)";
+
+/// The `max_uuid` of summaries that have not seen a complete entry yet
+const NO_UUID: &str = "(none)";
+
+/// The statistics we maintain about each query; we keep queries unique
+/// by `(query, subgraph)`
+///
+/// Changes to this data structure require that the summary files get
+/// regenerated from the processed log files by running `qlog process`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryInfo {
+    pub query: String,
+    pub subgraph: String,
+    /// The total time (in ms) spend on this query
+    pub total_time: u64,
+    /// The sum of query times squared, for computing standard deviation
+    pub time_squared: u64,
+    /// The longest a single instance of the query took
+    pub max_time: u64,
+    /// The UUID of the slowest query; this helps in finding that query
+    /// in the logfile
+    pub max_uuid: String,
+    /// The variables used in the slowest query
+    pub max_variables: String,
+    /// The estimated cost of the slowest query, see `Metrics::cost`
+    #[serde(default = "zero")]
+    pub max_complexity: u64,
+    /// The nesting depth of the slowest query, see `Metrics::depth`.
+    /// Summaries written before we computed metrics lack it; it is
+    /// `UNMEASURED` for them until `upgrade` has computed the metrics
+    #[serde(default = "unmeasured")]
+    pub depth: u64,
+    /// The number of fields in the slowest query, see `Metrics::fields`
+    #[serde(default)]
+    pub fields: u64,
+    /// The number of collections in the slowest query, see
+    /// `Metrics::collections`
+    #[serde(default)]
+    pub collections: u64,
+    /// The number of times this query took longer than `SLOW_THRESHOLD`
+    pub slow_count: u64,
+    /// The number of times the query has been run
+    pub calls: u64,
+    /// An ID to make it easier to refer to the query for the user
+    pub id: usize,
+    /// The hash value for this query; two `QueryInfo` instances with the
+    /// same `hash` are assumed to refer to the same logical query
+    #[serde(default = "zero")]
+    pub hash: u64,
+    /// The values of additional dimensions, like the graph-node instance,
+    /// by which queries with the same `hash` were grouped
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, String>,
+    /// What the schema of the subgraph tells us about the query; only
+    /// present if summaries were annotated with schemas
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<SchemaInfo>,
+    /// Only for summaries that were produced while keeping just the top
+    /// shapes: the statistic by which shapes were ranked might be this
+    /// much larger than what is recorded here, since entries for this
+    /// shape might have been dropped earlier to make room for others
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub error: u64,
+}
+
+fn zero() -> u64 {
+    0
+}
+
+/// The `depth` of summaries whose metrics have not been computed
+const UNMEASURED: u64 = u64::MAX;
+
+fn unmeasured() -> u64 {
+    UNMEASURED
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl QueryInfo {
+    pub fn new(
+        query: String,
+        subgraph: String,
+        id: usize,
+        hash: u64,
+        groups: BTreeMap<String, String>,
+    ) -> QueryInfo {
+        QueryInfo {
+            query,
+            subgraph,
+            id,
+            total_time: 0,
+            time_squared: 0,
+            max_time: 0,
+            max_uuid: NO_UUID.to_owned(),
+            max_variables: "null".to_owned(),
+            max_complexity: 0,
+            depth: 0,
+            fields: 0,
+            collections: 0,
+            slow_count: 0,
+            calls: 0,
+            hash,
+            groups,
+            schema: None,
+            error: 0,
+        }
+    }
+
+    pub fn add(&mut self, entry: &Entry) {
+        self.calls += 1;
+        self.total_time += entry.time;
+        self.time_squared += entry.time * entry.time;
+        // Truncated entries only count towards the totals since their
+        // query and variables are incomplete. The first complete entry
+        // always sets the slowest query, even if it took 0ms
+        if !entry.truncated && (entry.time > self.max_time || self.max_uuid == NO_UUID) {
+            self.max_time = entry.time;
+            self.max_uuid = entry.query_id.to_string();
+            self.max_variables = entry.variables.to_string();
+            self.query = entry.query.to_string();
+            self.measure();
+        }
+        if entry.time > SLOW_THRESHOLD {
+            self.slow_count += 1;
+        }
+    }
+
+    /// Compute the static metrics of `query` with `max_variables`
+    fn measure(&mut self) {
+        if self.is_truncated() {
+            return;
+        }
+        if let Some(doc) = shape_hash::parse(&self.query) {
+            let metrics = metrics::metrics(&doc, &self.max_variables);
+            self.max_complexity = metrics.cost;
+            self.depth = metrics.depth;
+            self.fields = metrics.fields;
+            self.collections = metrics.collections;
+        }
+    }
+
+    /// Whether this summary has only seen truncated entries, so that we
+    /// do not know its query
+    pub fn is_truncated(&self) -> bool {
+        self.query == TRUNCATED_QUERY
+    }
+
+    pub fn avg(&self) -> f64 {
+        self.total_time as f64 / self.calls as f64
+    }
+
+    pub fn variance(&self) -> f64 {
+        let avg = self.avg();
+        let calls = self.calls as f64;
+        let time_squared = self.time_squared as f64;
+        time_squared / calls - avg * avg
+    }
+
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    pub fn combine(&mut self, other: &QueryInfo) {
+        self.calls += other.calls;
+        self.total_time += other.total_time;
+        self.time_squared += other.time_squared;
+        if other.max_time > self.max_time || self.max_uuid == NO_UUID {
+            if self.is_truncated() {
+                self.query = other.query.clone();
+            }
+            self.max_time = other.max_time;
+            self.max_uuid = other.max_uuid.clone();
+            self.max_variables = other.max_variables.clone();
+            self.max_complexity = other.max_complexity;
+            self.depth = other.depth;
+            self.fields = other.fields;
+            self.collections = other.collections;
+        }
+        self.slow_count += other.slow_count;
+        self.error += other.error;
+        if self.schema.is_none() {
+            self.schema = other.schema.clone();
+        }
+    }
+
+    /// A hash value that can be calculated without constructing
+    /// a `QueryInfo`
+    pub fn hash(query_id: &str, query: &str, subgraph: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        if query_id.matches("-").count() == 1 {
+            // A new style query id in the format {shape_hash}-{hash}
+            let shape_hash = query_id.split("-").next().unwrap();
+            u64::from_str_radix(shape_hash, 16).map_err(|e| {
+                eprintln!(
+                    "query_id looks like it has the shape_hash, but apparently not: {}: {}",
+                    shape_hash, e
+                );
+            })
+        } else {
+            shape_hash::parse(query)
+                .ok_or(())
+                .map(|doc| shape_hash::shape_hash(&doc))
+        }
+        .map(|shape_hash|
+            // We have a shape_hash
+            (shape_hash, subgraph).hash(&mut hasher))
+        .unwrap_or_else(|_|
+            // Fall back to the old way of computing hashes
+            (query, subgraph).hash(&mut hasher));
+
+        hasher.finish()
+    }
+
+    /// The hash under which `entry` is aggregated, see `hash` and
+    /// `resolved_hash`. We can not compute the shape of a truncated
+    /// query; truncated entries therefore only get the hash of their
+    /// shape if the `query_id` contains it, and are otherwise lumped
+    /// together by subgraph
+    pub fn entry_hash(entry: &Entry, resolve_variables: bool) -> u64 {
+        if entry.truncated {
+            if !resolve_variables && entry.query_id.matches("-").count() == 1 {
+                QueryInfo::hash(&entry.query_id, TRUNCATED_QUERY, &entry.subgraph)
+            } else {
+                let mut hasher = DefaultHasher::new();
+                (TRUNCATED_QUERY, entry.subgraph.as_ref()).hash(&mut hasher);
+                hasher.finish()
+            }
+        } else if resolve_variables {
+            QueryInfo::resolved_hash(&entry.query, &entry.variables, &entry.subgraph)
+        } else {
+            QueryInfo::hash(&entry.query_id, &entry.query, &entry.subgraph)
+        }
+    }
+
+    /// Like `hash`, but substitute `variables` into the query before
+    /// computing its shape hash so that the keys of object-valued
+    /// variables become part of the shape. This always parses the query,
+    /// even if the `query_id` already contains the shape hash
+    pub fn resolved_hash(query: &str, variables: &str, subgraph: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        match shape_hash::parse(query) {
+            Some(mut doc) => {
+                shape_hash::substitute(&mut doc, variables);
+                (shape_hash::shape_hash(&doc), subgraph).hash(&mut hasher)
+            }
+            None => (query, subgraph).hash(&mut hasher),
+        }
+
+        hasher.finish()
+    }
+
+    /// The key under which we aggregate queries: the `hash` of the query
+    /// combined with the `(dimension, value)` pairs of its groups, which
+    /// must be sorted by dimension. Without groups, this is just `hash`
+    pub fn group_key<'a>(hash: u64, groups: impl IntoIterator<Item = (&'a str, &'a str)>) -> u64 {
+        let mut groups = groups.into_iter().peekable();
+        if groups.peek().is_none() {
+            return hash;
+        }
+        let mut hasher = DefaultHasher::new();
+        hash.hash(&mut hasher);
+        for group in groups {
+            group.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    pub fn key(&self) -> u64 {
+        QueryInfo::group_key(
+            self.hash,
+            self.groups
+                .iter()
+                .map(|(dim, value)| (dim.as_str(), value.as_str())),
+        )
+    }
+
+    pub fn read(line: &str) -> Result<QueryInfo, serde_json::Error> {
+        serde_json::from_str(line).map(QueryInfo::upgrade)
+    }
+
+    /// Fill in what summaries written by older versions lack
+    pub(crate) fn upgrade(mut self) -> QueryInfo {
+        if self.hash == 0 {
+            self.hash = QueryInfo::hash("ignore", &self.query, &self.subgraph);
+        }
+        if self.depth == UNMEASURED {
+            // Summaries written by older versions do not have metrics;
+            // if the query does not parse, they stay 0
+            self.depth = 0;
+            self.measure();
+        }
+        self
+    }
+}
+
+/// What we saw while reading one input with `Aggregator::read`
+#[derive(Debug, Default, Clone)]
+pub struct ReadStats {
+    /// The number of lines we read
+    pub lines: usize,
+    /// The number of lines that were queries
+    pub entries: usize,
+    /// How long reading took overall
+    pub elapsed: Duration,
+    /// How much of `elapsed` was spent parsing lines that were queries
+    pub parse_time: Duration,
+}
+
+impl ReadStats {
+    pub fn add(&mut self, other: &ReadStats) {
+        self.lines += other.lines;
+        self.entries += other.entries;
+        self.elapsed += other.elapsed;
+        self.parse_time += other.parse_time;
+    }
+}
+
+/// The statistic by which we rank shapes when we only keep the top ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weight {
+    TotalTime,
+    Calls,
+}
+
+impl Weight {
+    fn of(&self, info: &QueryInfo) -> u64 {
+        let weight = match self {
+            Weight::TotalTime => info.total_time,
+            Weight::Calls => info.calls,
+        };
+        weight + info.error
+    }
+}
+
+impl FromStr for Weight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "total_time" | "time" => Ok(Weight::TotalTime),
+            "calls" => Ok(Weight::Calls),
+            _ => Err(format!(
+                "unknown ranking `{}`; use one of total_time or calls",
+                s
+            )),
+        }
+    }
+}
+
+/// Aggregates entries into summaries, one for each distinct query shape
+/// and group. Entries can be added one at a time with `add`, or read from
+/// any `BufRead` with `read`, and `summaries` can be called at any point
+/// to get a snapshot of the summaries so far
+#[derive(Default)]
+pub struct Aggregator {
+    queries: BTreeMap<u64, QueryInfo>,
+    grouping: Grouping,
+    /// Whether to substitute variables into queries before computing
+    /// their shape hash
+    resolve_variables: bool,
+    /// The maximum number of summaries to keep and how to rank them
+    limit: Option<(usize, Weight)>,
+    /// `(weight, key)` for all summaries when there is a `limit`, so we
+    /// can quickly find the one with the lowest weight
+    ranks: BTreeSet<(u64, u64)>,
+    /// How many summaries were dropped because of the `limit`
+    evicted: u64,
+    next_id: usize,
+}
+
+impl Aggregator {
+    pub fn new(grouping: Grouping) -> Self {
+        Aggregator {
+            grouping,
+            ..Default::default()
+        }
+    }
+
+    /// Substitute the variables of each entry into its query before
+    /// computing the shape hash; see `QueryInfo::resolved_hash`
+    pub fn resolve_variables(&mut self, resolve: bool) {
+        self.resolve_variables = resolve;
+    }
+
+    /// Only keep the `capacity` summaries with the highest `weight` so
+    /// that memory use stays bounded no matter how many distinct shapes
+    /// there are. This uses the Space-Saving algorithm: when a new shape
+    /// comes along and there is no more room, the summary with the lowest
+    /// weight is dropped, and the weight it had becomes the `error` of the
+    /// new summary. Every shape whose true weight is more than the total
+    /// weight of all entries divided by `capacity` is guaranteed to be
+    /// kept. This must be called before any entries are added
+    pub fn limit(&mut self, capacity: usize, weight: Weight) {
+        self.limit = Some((capacity.max(1), weight));
+    }
+
+    /// How many summaries were dropped because of the limit
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Drop the summary with the lowest weight if there is no room for
+    /// another one, and return its weight
+    fn make_room(&mut self) -> u64 {
+        let capacity = match self.limit {
+            Some((capacity, _)) => capacity,
+            None => return 0,
+        };
+        let mut error = 0;
+        while self.queries.len() >= capacity {
+            match self.ranks.pop_first() {
+                Some((weight, key)) => {
+                    self.queries.remove(&key);
+                    self.evicted += 1;
+                    error = weight;
+                }
+                None => break,
+            }
+        }
+        error
+    }
+
+    pub fn add(&mut self, entry: &Entry) {
+        let hsh = QueryInfo::entry_hash(entry, self.resolve_variables);
+        let groups = self.grouping.groups(entry);
+        let key = QueryInfo::group_key(hsh, groups.iter().cloned());
+        if !self.queries.contains_key(&key) {
+            let groups = groups
+                .iter()
+                .map(|(dim, value)| (dim.to_string(), value.to_string()))
+                .collect();
+            let error = self.make_room();
+            self.next_id += 1;
+            let query = if entry.truncated {
+                TRUNCATED_QUERY.to_owned()
+            } else {
+                entry.query.to_string()
+            };
+            let mut info =
+                QueryInfo::new(query, entry.subgraph.to_string(), self.next_id, hsh, groups);
+            info.error = error;
+            self.queries.insert(key, info);
+        }
+
+        let info = self
+            .queries
+            .get_mut(&key)
+            .expect("we just made sure it exists");
+        match self.limit {
+            Some((_, weight)) => {
+                self.ranks.remove(&(weight.of(info), key));
+                info.add(entry);
+                self.ranks.insert((weight.of(info), key));
+            }
+            None => info.add(entry),
+        }
+    }
+
+    /// Parse every line of `reader` with `parser` and add the resulting
+    /// entries. For each line, `visit` is called with the line and the
+    /// entry, if the line was a query, after the entry was added
+    pub fn read<R, F>(
+        &mut self,
+        reader: R,
+        parser: &dyn EntryParser,
+        mut visit: F,
+    ) -> Result<ReadStats, io::Error>
+    where
+        R: BufRead,
+        F: FnMut(&str, Option<&Entry>) -> Result<(), io::Error>,
+    {
+        let start = Instant::now();
+        let mut stats = ReadStats::default();
+        for line in reader.lines() {
+            let line = line?;
+            stats.lines += 1;
+
+            let parse_start = Instant::now();
+            let entry = parser.parse(&line);
+            if let Some(entry) = &entry {
+                stats.parse_time += parse_start.elapsed();
+                stats.entries += 1;
+                self.add(entry);
+            }
+            visit(&line, entry.as_ref())?;
+        }
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+
+    /// The number of distinct summaries
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    /// A snapshot of the summaries of all entries added so far
+    pub fn summaries(&self) -> Vec<QueryInfo> {
+        self.queries.values().cloned().collect()
+    }
+
+    pub fn into_summaries(self) -> Vec<QueryInfo> {
+        self.queries.into_values().collect()
+    }
+}
+
+/// Sort `queries` by the column `sort`, largest first. Columns can be
+/// abbreviated to their first letter, except for the static metrics
+/// `complexity`, `depth`, `fields` and `collections`
+pub fn sort_summaries(queries: &mut [QueryInfo], sort: &str) {
+    // The static metrics need to be spelled out since their first letters
+    // are taken by other columns
+    let metric = |info: &QueryInfo| match sort {
+        "complexity" | "cost" => Some(info.max_complexity),
+        "depth" => Some(info.depth),
+        "fields" => Some(info.fields),
+        "collections" => Some(info.collections),
+        _ => None,
+    };
+    let sort = sort.chars().next().unwrap_or('t');
+    queries.sort_by(|a, b| {
+        let ord = match sort {
+            _ if metric(a).is_some() => metric(a).cmp(&metric(b)),
+            'c' => a.calls.cmp(&b.calls),
+            'a' => a.avg().partial_cmp(&b.avg()).unwrap(),
+            'm' => a.max_time.cmp(&b.max_time),
+            's' => a.slow_count.cmp(&b.slow_count),
+            'u' => a.max_uuid.cmp(&b.max_uuid),
+            _ => a.total_time.cmp(&b.total_time),
+        };
+        ord.reverse()
+    });
+}
+
+/// The totals for all the shapes of one subgraph
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Rollup {
+    pub subgraph: String,
+    pub shapes: u64,
+    pub calls: u64,
+    pub total_time: u64,
+    pub max_time: u64,
+    pub slow_count: u64,
+}
+
+/// The totals for each subgraph in `infos`, most expensive first
+pub fn rollup(infos: &[QueryInfo]) -> Vec<Rollup> {
+    let mut rollups: BTreeMap<&str, Rollup> = BTreeMap::new();
+    for info in infos {
+        let rollup = rollups
+            .entry(info.subgraph.as_str())
+            .or_insert_with(|| Rollup {
+                subgraph: info.subgraph.clone(),
+                ..Default::default()
+            });
+        rollup.shapes += 1;
+        rollup.calls += info.calls;
+        rollup.total_time += info.total_time;
+        rollup.max_time = rollup.max_time.max(info.max_time);
+        rollup.slow_count += info.slow_count;
+    }
+    let mut rollups: Vec<_> = rollups.into_values().collect();
+    rollups.sort_by_key(|rollup| std::cmp::Reverse(rollup.total_time));
+    rollups
+}
+
+/// Read a list of summaries from `filename` The file must either be in
+/// 'JSON lines' format, i.e., with one JSON object per line, in the
+/// Parquet format written by `columnar::write_summaries`, or in the
+/// compact format written by `compact::write_summaries`
+pub fn read_summaries(filename: &str) -> Result<Vec<QueryInfo>, std::io::Error> {
+    let mut file = File::open(filename)?;
+    let mut magic = [0u8; 4];
+    let magic = match file.read_exact(&mut magic) {
+        Ok(()) => &magic[..],
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => &[],
+        Err(e) => return Err(e),
+    };
+    file.seek(SeekFrom::Start(0))?;
+    if magic == columnar::MAGIC {
+        columnar::read_summaries(file)
+    } else if magic == compact::MAGIC {
+        compact::read_summaries(BufReader::new(file))
+    } else {
+        read_summaries_from(BufReader::new(file))
+    }
+}
+
+/// Read a list of summaries in 'JSON lines' format from `reader`
+pub fn read_summaries_from<R: BufRead>(reader: R) -> Result<Vec<QueryInfo>, std::io::Error> {
+    let mut infos = vec![];
+    for line in reader.lines() {
+        infos.push(QueryInfo::read(&line?)?);
+    }
+    Ok(infos)
+}
+
+/// Write a list of summaries to `writer`; the list will be written in
+/// 'JSON lines' format
+pub fn write_summaries(writer: &mut dyn Write, infos: Vec<QueryInfo>) -> Result<(), io::Error> {
+    for info in infos {
+        let json = serde_json::to_string(&info)?;
+        writeln!(writer, "{}", json)?;
+    }
+    Ok(())
+}
+
+/// Combine summaries for the same query and group into one, and number
+/// the resulting summaries consecutively
+pub fn combine(infos: impl IntoIterator<Item = QueryInfo>) -> Vec<QueryInfo> {
+    let mut combined: BTreeMap<u64, QueryInfo> = BTreeMap::default();
+    for info in infos {
+        combined
+            .entry(info.key())
+            .and_modify(|existing| existing.combine(&info))
+            .or_insert(info);
+    }
+    for (indx, value) in combined.values_mut().enumerate() {
+        value.id = indx;
+    }
+    combined.into_values().collect()
+}
+
+/// Combine summaries that only differ in the values of dimensions other
+/// than `dims`
+pub fn regroup(infos: Vec<QueryInfo>, dims: &[&str]) -> Vec<QueryInfo> {
+    let mut grouped: BTreeMap<u64, QueryInfo> = BTreeMap::default();
+    for mut info in infos {
+        info.groups.retain(|dim, _| dims.contains(&dim.as_str()));
+        grouped
+            .entry(info.key())
+            .and_modify(|existing| existing.combine(&info))
+            .or_insert(info);
+    }
+    grouped.into_values().collect()
+}
+
+/// Entries and summaries for the tests of all modules
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    /// An entry for the query `{ <field> { id } }` that took `time` ms
+    pub fn entry(field: &str, time: u64) -> Entry<'static> {
+        Entry {
+            subgraph: "QmSuBgRaPh".into(),
+            query_id: "f2-6b-48-b6-6b".into(),
+            block: 10344025,
+            time,
+            query: format!("{{ {} {{ id }} }}", field).into(),
+            variables: "{}".into(),
+            timestamp: None,
+            truncated: false,
+            origin: None,
+        }
+    }
+
+    /// The summary `Q<id>` for `calls` calls of `query` that took
+    /// `total_time` ms altogether, all of it in the slowest call
+    pub fn info(id: usize, subgraph: &str, query: &str, calls: u64, total_time: u64) -> QueryInfo {
+        let mut info = QueryInfo::new(
+            query.to_owned(),
+            subgraph.to_owned(),
+            id,
+            QueryInfo::hash("ignore", query, subgraph),
+            BTreeMap::new(),
+        );
+        info.calls = calls;
+        info.total_time = total_time;
+        info.max_time = total_time;
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::entry;
+    use super::*;
+
+    #[test]
+    fn top_shapes() {
+        let mut aggregator = Aggregator::new(Grouping::default());
+        aggregator.limit(2, Weight::TotalTime);
+        for (field, time) in &[("a", 100), ("b", 10), ("a", 100), ("c", 5), ("d", 50)] {
+            aggregator.add(&entry(field, *time));
+        }
+
+        // `b` made room for `c`, which in turn made room for `d`
+        let mut summaries = aggregator.summaries();
+        summaries.sort_by_key(|info| info.id);
+        assert_eq!(2, aggregator.evicted());
+        assert_eq!(2, summaries.len());
+        assert_eq!("{ a { id } }", summaries[0].query);
+        assert_eq!((200, 0), (summaries[0].total_time, summaries[0].error));
+        assert_eq!("{ d { id } }", summaries[1].query);
+        assert_eq!((50, 15), (summaries[1].total_time, summaries[1].error));
+        assert_eq!(4, summaries[1].id);
+    }
+
+    #[test]
+    fn truncated_entries() {
+        let truncated = |query_id: &'static str, query: &'static str, time| Entry {
+            query_id: query_id.into(),
+            query: query.into(),
+            truncated: true,
+            ..entry("a", time)
+        };
+
+        let mut aggregator = Aggregator::new(Grouping::default());
+        // Without the shape hash in the query_id, all truncated entries
+        // of a subgraph are aggregated together, wherever they were cut
+        aggregator.add(&truncated("(unknown)", "{ a { i", 500));
+        aggregator.add(&truncated("(unknown)", "{ b ", 20));
+        // With it, they are aggregated with complete entries, but do not
+        // become the slowest query
+        aggregator.add(&truncated("1f-2a", "{ c { id ", 900));
+        aggregator.add(&Entry {
+            query_id: "1f-2b".into(),
+            ..entry("c", 10)
+        });
+
+        let mut summaries = aggregator.summaries();
+        summaries.sort_by_key(|info| info.id);
+        assert_eq!(2, summaries.len());
+        assert_eq!(TRUNCATED_QUERY, summaries[0].query);
+        assert!(summaries[0].is_truncated() && !summaries[1].is_truncated());
+        assert_eq!((2, 520), (summaries[0].calls, summaries[0].total_time));
+        assert_eq!(NO_UUID, summaries[0].max_uuid);
+        assert_eq!("{ c { id } }", summaries[1].query);
+        assert_eq!((2, 910), (summaries[1].calls, summaries[1].total_time));
+        assert_eq!(
+            (10, "1f-2b"),
+            (summaries[1].max_time, summaries[1].max_uuid.as_str())
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Follow how the latency of a single query shape evolves over time. We
+//! put the entries for the shape into buckets of a fixed length by their
+//! timestamp and compute the number of calls and the average, maximum
+//! and 95th percentile of their time for each bucket
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::extract::rfc3339_from_millis;
+use crate::{Entry, QueryInfo};
+
+/// The statistics for one bucket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Row {
+    /// The start of the bucket as an RFC 3339 timestamp in UTC
+    pub start: String,
+    pub count: u64,
+    /// The average time (in ms) of queries in the bucket
+    pub avg: f64,
+    pub max: u64,
+    pub p95: u64,
+}
+
+/// Parse an interval like `30s`, `5m`, `1h` or `1d` into milliseconds; a
+/// plain number is a number of seconds
+pub fn parse_interval(interval: &str) -> Result<u64, String> {
+    let (num, unit) = match interval.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => interval.split_at(pos),
+        None => (interval, "s"),
+    };
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("invalid unit in interval {}", interval)),
+    };
+    match num.parse::<u64>() {
+        Ok(num) if num > 0 => Ok(num * secs * 1000),
+        _ => Err(format!("invalid interval {}", interval)),
+    }
+}
+
+/// Parse an RFC 3339 timestamp like `2020-12-31T23:59:59.739Z` into
+/// milliseconds since the Unix epoch
+pub fn parse_timestamp(ts: &str) -> Option<u64> {
+    fn num(s: &str, range: std::ops::Range<usize>) -> Option<i64> {
+        let digits = s.get(range)?;
+        if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    let year = num(ts, 0..4)?;
+    let month = num(ts, 5..7)?;
+    let day = num(ts, 8..10)?;
+    let hour = num(ts, 11..13)?;
+    let minute = num(ts, 14..16)?;
+    let second = num(ts, 17..19)?;
+    let rest = &ts[19..];
+    let frac_len = rest
+        .strip_prefix('.')
+        .map(|frac| frac.bytes().take_while(u8::is_ascii_digit).count())
+        .unwrap_or(0);
+    let millis = if frac_len > 0 {
+        // Pad or cut the fraction to three digits
+        let frac = format!("{:0<3}", &rest[1..1 + frac_len.min(3)]);
+        frac.parse::<i64>().ok()?
+    } else {
+        0
+    };
+    let tz = if frac_len > 0 {
+        &rest[1 + frac_len..]
+    } else {
+        rest
+    };
+    let offset = match tz {
+        "Z" | "z" => 0,
+        tz if tz.len() == 6 && (tz.starts_with('+') || tz.starts_with('-')) => {
+            let secs = num(tz, 1..3)? * 3600 + num(tz, 4..6)? * 60;
+            if tz.starts_with('-') {
+                -secs
+            } else {
+                secs
+            }
+        }
+        _ => return None,
+    };
+
+    // Convert the civil date to days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    let millis = secs * 1000 + millis;
+    if millis >= 0 {
+        Some(millis as u64)
+    } else {
+        None
+    }
+}
+
+/// Collects the times of all entries for one shape by bucket
+#[derive(Debug)]
+pub struct TimeSeries {
+    hash: u64,
+    /// The length of a bucket in ms
+    interval: u64,
+    resolve_variables: bool,
+    /// The times of the entries in each bucket, keyed by the start of
+    /// the bucket in ms since the epoch
+    buckets: BTreeMap<u64, Vec<u64>>,
+    /// The number of entries for the shape that we had to ignore since
+    /// they did not have a timestamp we could understand
+    pub skipped: usize,
+}
+
+impl TimeSeries {
+    /// A time series for the entries whose `QueryInfo::hash` is `hash`
+    /// with buckets that are `interval` ms long
+    pub fn new(hash: u64, interval: u64) -> Self {
+        TimeSeries {
+            hash,
+            interval,
+            resolve_variables: false,
+            buckets: BTreeMap::new(),
+            skipped: 0,
+        }
+    }
+
+    /// Use `QueryInfo::resolved_hash` to match entries; this must be the
+    /// same as what the summaries the shape was taken from used
+    pub fn resolve_variables(&mut self, resolve: bool) {
+        self.resolve_variables = resolve;
+    }
+
+    pub fn add(&mut self, entry: &Entry) {
+        let hash = QueryInfo::entry_hash(entry, self.resolve_variables);
+        if hash != self.hash {
+            return;
+        }
+        match entry.timestamp.as_deref().and_then(parse_timestamp) {
+            Some(ts) => self
+                .buckets
+                .entry(ts - ts % self.interval)
+                .or_default()
+                .push(entry.time),
+            None => self.skipped += 1,
+        }
+    }
+
+    /// The statistics for each bucket that has entries, oldest first
+    pub fn rows(&self) -> Vec<Row> {
+        self.buckets
+            .iter()
+            .map(|(start, times)| {
+                let mut times = times.clone();
+                times.sort_unstable();
+                let count = times.len() as u64;
+                // The nearest-rank percentile
+                let p95 = times[(times.len() * 95).div_ceil(100) - 1];
+                Row {
+                    start: rfc3339_from_millis(*start),
+                    count,
+                    avg: times.iter().sum::<u64>() as f64 / count as f64,
+                    max: *times.last().unwrap(),
+                    p95,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(query: &str, time: u64, timestamp: Option<&'static str>) -> Entry<'static> {
+        Entry {
+            subgraph: "QmSuBgRaPh".into(),
+            query_id: "f2-6b-48-b6-6b".into(),
+            block: 10344025,
+            time,
+            query: query.to_owned().into(),
+            variables: "{}".into(),
+            timestamp: timestamp.map(Into::into),
+            truncated: false,
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn buckets() {
+        assert_eq!(Ok(300_000), parse_interval("5m"));
+        assert_eq!(Ok(30_000), parse_interval("30"));
+        assert!(parse_interval("5w").is_err());
+        assert!(parse_interval("0m").is_err());
+
+        assert_eq!(Some(0), parse_timestamp("1970-01-01T00:00:00Z"));
+        assert_eq!(
+            Some(951827696789),
+            parse_timestamp("2000-02-29T12:34:56.789Z")
+        );
+        assert_eq!(
+            Some(951827696789),
+            parse_timestamp("2000-02-29T14:34:56.789123+02:00")
+        );
+        assert_eq!(None, parse_timestamp("2000-02-29 12:34"));
+
+        let query = "{ things { id } }";
+        let hash = QueryInfo::hash("f2-6b-48-b6-6b", query, "QmSuBgRaPh");
+        let mut series = TimeSeries::new(hash, parse_interval("5m").unwrap());
+        for (time, ts) in &[
+            (10, "2024-05-01T10:01:00Z"),
+            (30, "2024-05-01T10:04:59.999Z"),
+            (20, "2024-05-01T10:02:00Z"),
+            (100, "2024-05-01T10:17:00Z"),
+        ] {
+            series.add(&entry(query, *time, Some(ts)));
+        }
+        series.add(&entry(query, 5, None));
+        series.add(&entry("{ stuff { id } }", 5, Some("2024-05-01T10:01:00Z")));
+
+        assert_eq!(1, series.skipped);
+        let row = |start: &str, count, avg, max, p95| Row {
+            start: start.to_owned(),
+            count,
+            avg,
+            max,
+            p95,
+        };
+        assert_eq!(
+            vec![
+                row("2024-05-01T10:00:00.000Z", 3, 20.0, 30, 30),
+                row("2024-05-01T10:15:00.000Z", 1, 100.0, 100, 100),
+            ],
+            series.rows()
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! An interactive terminal UI for browsing summaries: a table of all
+//! summaries that can be sorted and filtered, and a pane with the details
+//! of the selected summary
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::io::{self, Write};
+
+use crate::pretty;
+use crate::{sort_summaries, QueryInfo};
+
+/// The columns the table can be sorted by, in the order in which `s`
+/// cycles through them
+const COLUMNS: &[&str] = &[
+    "total_time",
+    "calls",
+    "avg",
+    "max",
+    "slow",
+    "complexity",
+    "depth",
+];
+
+const HELP: &str =
+    "q quit  ↑↓ select  s/S sort  / filter  PgUp/PgDn scroll details  y copy max_uuid";
+
+struct App<'a> {
+    /// All summaries, sorted by the current column
+    infos: Vec<QueryInfo>,
+    /// Indices into `infos` of the rows that match `filter`
+    rows: Vec<usize>,
+    /// The index of the current sort column in `COLUMNS`
+    sort: usize,
+    filter: String,
+    /// Whether keys are currently typed into the filter
+    editing: bool,
+    table: TableState,
+    /// How far the details pane is scrolled down
+    scroll: u16,
+    status: String,
+    details: &'a dyn Fn(&QueryInfo) -> String,
+}
+
+impl<'a> App<'a> {
+    fn new(infos: Vec<QueryInfo>, details: &'a dyn Fn(&QueryInfo) -> String) -> Self {
+        let mut app = App {
+            infos,
+            rows: Vec::new(),
+            sort: 0,
+            filter: String::new(),
+            editing: false,
+            table: TableState::default(),
+            scroll: 0,
+            status: HELP.to_owned(),
+            details,
+        };
+        app.sort_by(0);
+        app
+    }
+
+    fn selected(&self) -> Option<&QueryInfo> {
+        self.table
+            .selected()
+            .and_then(|row| self.rows.get(row))
+            .map(|indx| &self.infos[*indx])
+    }
+
+    /// Sort by `COLUMNS[column]`, keeping the same summary selected
+    fn sort_by(&mut self, column: usize) {
+        let id = self.selected().map(|info| info.id);
+        self.sort = column % COLUMNS.len();
+        sort_summaries(&mut self.infos, COLUMNS[self.sort]);
+        self.apply_filter(id);
+    }
+
+    /// Recompute `rows` from `filter` and select the row for the summary
+    /// with `id`, or the first row if that does not match any more
+    fn apply_filter(&mut self, id: Option<usize>) {
+        let filter = self.filter.to_lowercase();
+        self.rows = self
+            .infos
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| {
+                filter.is_empty()
+                    || format!("q{}", info.id) == filter
+                    || info.subgraph.to_lowercase().contains(&filter)
+                    || info.query.to_lowercase().contains(&filter)
+            })
+            .map(|(indx, _)| indx)
+            .collect();
+        let row = id
+            .and_then(|id| self.rows.iter().position(|indx| self.infos[*indx].id == id))
+            .unwrap_or(0);
+        self.table.select(if self.rows.is_empty() {
+            None
+        } else {
+            Some(row)
+        });
+    }
+
+    fn select(&mut self, delta: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let row = self.table.selected().unwrap_or(0) as isize + delta;
+        let row = row.clamp(0, self.rows.len() as isize - 1) as usize;
+        if Some(row) != self.table.selected() {
+            self.table.select(Some(row));
+            self.scroll = 0;
+        }
+    }
+
+    /// Handle a key press; return `false` when we should quit
+    fn key(&mut self, code: KeyCode) -> bool {
+        if self.editing {
+            match code {
+                KeyCode::Enter | KeyCode::Esc => {
+                    self.editing = false;
+                    self.status = HELP.to_owned();
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => {}
+            }
+            let id = self.selected().map(|info| info.id);
+            self.apply_filter(id);
+            return true;
+        }
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::Home => self.select(-(self.rows.len() as isize)),
+            KeyCode::End => self.select(self.rows.len() as isize),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Char('s') => self.sort_by(self.sort + 1),
+            KeyCode::Char('S') => self.sort_by(self.sort + COLUMNS.len() - 1),
+            KeyCode::Char('/') => {
+                self.editing = true;
+                self.status = "Enter finishes the filter".to_owned();
+            }
+            KeyCode::Char('y') => {
+                if let Some(uuid) = self.selected().map(|info| info.max_uuid.clone()) {
+                    self.status = match copy(&uuid) {
+                        Ok(()) => format!("Copied {}", uuid),
+                        Err(e) => format!("Could not copy {}: {}", uuid, e),
+                    };
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [top, bottom, footer] = Layout::vertical([
+            Constraint::Percentage(50),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let header = [
+            "QID",
+            "subgraph",
+            "calls",
+            "complexity",
+            "depth",
+            "total",
+            "avg",
+            "max",
+            "slow",
+        ];
+        let header = Row::new(header.iter().map(|name| {
+            let column = match *name {
+                "total" => "total_time",
+                name => name,
+            };
+            if column == COLUMNS[self.sort] {
+                format!("{}▼", name)
+            } else {
+                name.to_string()
+            }
+        }))
+        .bold();
+        let rows = self.rows.iter().map(|indx| {
+            let info = &self.infos[*indx];
+            Row::new(vec![
+                format!("Q{:0>6}", info.id),
+                info.subgraph.clone(),
+                info.calls.to_string(),
+                info.max_complexity.to_string(),
+                info.depth.to_string(),
+                info.total_time.to_string(),
+                format!("{:.0}", info.avg()),
+                info.max_time.to_string(),
+                info.slow_count.to_string(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(7),
+            Constraint::Min(12),
+            Constraint::Length(10),
+            Constraint::Length(11),
+            Constraint::Length(6),
+            Constraint::Length(12),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(6),
+        ];
+        let title = format!(" {} of {} summaries ", self.rows.len(), self.infos.len());
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table, top, &mut self.table);
+
+        let text = match self.selected() {
+            Some(info) => format!("{}\n{}", (self.details)(info), pretty::query(&info.query)),
+            None => String::new(),
+        };
+        let details = Paragraph::new(text)
+            .block(Block::bordered())
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        frame.render_widget(details, bottom);
+
+        let footer_text = if self.editing || !self.filter.is_empty() {
+            format!("/{}  {}", self.filter, self.status)
+        } else {
+            self.status.clone()
+        };
+        frame.render_widget(Line::from(footer_text).reversed(), footer);
+    }
+}
+
+/// Copy `text` to the clipboard with the OSC 52 escape sequence, which
+/// most terminals support and which also works over ssh
+fn copy(text: &str) -> Result<(), io::Error> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in text.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", encoded)?;
+    stdout.flush()
+}
+
+fn run_app(terminal: &mut DefaultTerminal, mut app: App) -> Result<(), io::Error> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !app.key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Browse `infos` until the user quits. The details pane shows what
+/// `details` returns for the selected summary, followed by its query
+pub fn run(infos: Vec<QueryInfo>, details: &dyn Fn(&QueryInfo) -> String) -> Result<(), io::Error> {
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, App::new(infos, details));
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::fixtures::info;
+
+    #[test]
+    fn sort_and_filter() {
+        let infos = vec![
+            info(1, "QmSuBgRaPh", "{ things { id } }", 10, 100),
+            info(2, "QmSuBgRaPh", "{ stuff { id } }", 1, 300),
+            info(3, "QmSuBgRaPh", "{ things { name } }", 5, 200),
+        ];
+        let details = |info: &QueryInfo| format!("Q{}", info.id);
+        let mut app = App::new(infos, &details);
+        let ids =
+            |app: &App| -> Vec<usize> { app.rows.iter().map(|indx| app.infos[*indx].id).collect() };
+        assert_eq!(vec![2, 3, 1], ids(&app));
+
+        // Sorting keeps the selection
+        app.key(KeyCode::Down);
+        app.key(KeyCode::Char('s'));
+        assert_eq!(vec![1, 3, 2], ids(&app));
+        assert_eq!(Some(3), app.selected().map(|info| info.id));
+
+        for key in "/THINGS".chars() {
+            app.key(KeyCode::Char(key));
+        }
+        app.key(KeyCode::Enter);
+        assert_eq!(vec![1, 3], ids(&app));
+        assert_eq!(Some(3), app.selected().map(|info| info.id));
+        assert!(app.key(KeyCode::Char('s')));
+        assert!(!app.key(KeyCode::Char('q')));
+    }
+}
@@ -0,0 +1,146 @@
+use qlog::{
+    combine, read_summaries_from, regroup, write_summaries, Aggregator, Dimension, EntryParser,
+    Grouping, JsonlEntryParser, QueryInfo, TextEntryParser,
+};
+
+const LOG: &str = "\
+Dec 31 23:59:59.739 INFO Query timing (GraphQL), block: 10344025, query_time_ms: 14, variables: null, query: query TranscoderQuery { transcoders(first: 1) { id } } , query_id: c5-d3-4e-92-37, subgraph_id: QmeYBGccAwahY, component: GraphQlRunner
+Dec 31 23:59:59.740 INFO Query timing (GraphQL), block: 10344025, query_time_ms: 1400, variables: null, query: query TranscoderQuery { transcoders(first: 5) { id } } , query_id: c5-d3-4e-92-38, subgraph_id: QmeYBGccAwahY, component: GraphQlRunner
+Dec 31 23:59:59.741 INFO Some other log line
+Dec 31 23:59:59.846 INFO Query timing (GraphQL), block: 10344025, query_time_ms: 12, variables: {\"id\":\"0xdeadbeef\"}, query: query exchange($id: String!) { exchange(id: $id) { id tokenAddress } } , query_id: c8-1c-4c-98-65, subgraph_id: QmSuBgRaPh, component: GraphQlRunner
+";
+
+fn aggregate(lines: &[&str], parser: &dyn EntryParser, grouping: Grouping) -> Vec<QueryInfo> {
+    let mut aggregator = Aggregator::new(grouping);
+    for line in lines {
+        if let Some(entry) = parser.parse(line) {
+            aggregator.add(&entry);
+        }
+    }
+    aggregator.into_summaries()
+}
+
+fn find<'a>(infos: &'a [QueryInfo], subgraph: &str) -> &'a QueryInfo {
+    infos
+        .iter()
+        .find(|info| info.subgraph == subgraph)
+        .expect("there is a summary for the subgraph")
+}
+
+#[test]
+fn summarize_text_log() {
+    let lines: Vec<&str> = LOG.lines().collect();
+    let infos = aggregate(&lines, &TextEntryParser {}, Grouping::default());
+
+    // Both transcoder queries have the same shape
+    assert_eq!(2, infos.len());
+    let transcoders = find(&infos, "QmeYBGccAwahY");
+    assert_eq!(2, transcoders.calls);
+    assert_eq!(1414, transcoders.total_time);
+    assert_eq!(1400, transcoders.max_time);
+    assert_eq!(1, transcoders.slow_count);
+    assert_eq!("c5-d3-4e-92-38", transcoders.max_uuid);
+    assert_eq!(
+        "query TranscoderQuery { transcoders(first: 5) { id } }",
+        transcoders.query
+    );
+    // The static metrics are those of the slowest query
+    assert_eq!(5, transcoders.max_complexity);
+    assert_eq!(2, transcoders.depth);
+    assert_eq!(1, transcoders.collections);
+
+    let exchange = find(&infos, "QmSuBgRaPh");
+    assert_eq!(1, exchange.calls);
+    assert_eq!("{\"id\":\"0xdeadbeef\"}", exchange.max_variables);
+}
+
+#[test]
+fn write_read_and_combine() {
+    let lines: Vec<&str> = LOG.lines().collect();
+    let all = aggregate(&lines, &TextEntryParser {}, Grouping::default());
+    let first = aggregate(&lines[..2], &TextEntryParser {}, Grouping::default());
+    let second = aggregate(&lines[2..], &TextEntryParser {}, Grouping::default());
+
+    let mut buf = Vec::new();
+    write_summaries(&mut buf, first).unwrap();
+    write_summaries(&mut buf, second).unwrap();
+    let read = read_summaries_from(buf.as_slice()).unwrap();
+    assert_eq!(2, read.len());
+
+    let combined = combine(read);
+    assert_eq!(all.len(), combined.len());
+    for info in &all {
+        let other = find(&combined, &info.subgraph);
+        assert_eq!(info.hash, other.hash);
+        assert_eq!(info.calls, other.calls);
+        assert_eq!(info.total_time, other.total_time);
+        assert_eq!(info.time_squared, other.time_squared);
+    }
+}
+
+#[test]
+fn group_by_node() {
+    let lines = [
+        r#"{"subgraph":"QmA","query_id":"aaaa-1","block":1,"time":100,"query":"{ things { id } }","variables":"null","origin":"node-1"}"#,
+        r#"{"subgraph":"QmA","query_id":"aaaa-2","block":1,"time":3000,"query":"{ things { id } }","variables":"null","origin":"node-2"}"#,
+        r#"{"subgraph":"QmA","query_id":"aaaa-3","block":1,"time":200,"query":"{ things { id } }","variables":"null","origin":"node-1"}"#,
+    ];
+    let grouping = Grouping::new(vec![Dimension::Node]);
+    let infos = aggregate(&lines, &JsonlEntryParser {}, grouping);
+
+    assert_eq!(2, infos.len());
+    let node1 = infos
+        .iter()
+        .find(|info| info.groups.get("node").map(String::as_str) == Some("node-1"))
+        .unwrap();
+    assert_eq!(2, node1.calls);
+    assert_eq!(300, node1.total_time);
+
+    // Dropping the node dimension adds the groups back up
+    let infos = regroup(infos, &[]);
+    assert_eq!(1, infos.len());
+    assert_eq!(3, infos[0].calls);
+    assert!(infos[0].groups.is_empty());
+}
+
+#[test]
+fn read_with_snapshots() {
+    let mut aggregator = Aggregator::default();
+    let mut others = vec![];
+    let stats = aggregator
+        .read(LOG.as_bytes(), &TextEntryParser {}, |line, entry| {
+            if entry.is_none() {
+                others.push(line.to_owned());
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(4, stats.lines);
+    assert_eq!(3, stats.entries);
+    assert_eq!(vec!["Dec 31 23:59:59.741 INFO Some other log line"], others);
+
+    // Taking a snapshot does not disturb further aggregation
+    let snapshot = aggregator.summaries();
+    assert_eq!(2, find(&snapshot, "QmeYBGccAwahY").calls);
+
+    let first_line = LOG.lines().next().unwrap();
+    aggregator
+        .read(first_line.as_bytes(), &TextEntryParser {}, |_, _| Ok(()))
+        .unwrap();
+    assert_eq!(2, find(&snapshot, "QmeYBGccAwahY").calls);
+    assert_eq!(3, find(&aggregator.summaries(), "QmeYBGccAwahY").calls);
+}
+
+#[test]
+fn upgrade_old_summaries() {
+    let old = r#"{"query":"{ things(first: 5) { id } }","subgraph":"QmA","total_time":10,"time_squared":100,"max_time":10,"max_uuid":"aaaa-1","max_variables":"null","slow_count":0,"calls":1,"id":1}"#;
+    let info = QueryInfo::read(old).unwrap();
+    assert_ne!(0, info.hash);
+    assert_eq!((2, 1), (info.depth, info.collections));
+
+    // Summaries that have metrics are left alone, even if they are 0
+    let mut json = serde_json::to_value(&info).unwrap();
+    json["depth"] = 0.into();
+    let info = QueryInfo::read(&json.to_string()).unwrap();
+    assert_eq!((0, 1), (info.depth, info.collections));
+}